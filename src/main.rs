@@ -6,6 +6,7 @@ mod webserver;
 use config::Config;
 use log::*;
 use settings::Settings;
+use webserver::shared::request_id;
 use webserver::WebServer;
 
 lazy_static::lazy_static! {
@@ -19,6 +20,9 @@ lazy_static::lazy_static! {
         conf.set_default("read_timeout", 5000).unwrap();
         conf.set_default("write_timeout", 5000).unwrap();
         conf.set_default("max_request_size", 8192).unwrap();
+        conf.set_default("max_body_size", 256 << 20).unwrap();
+        conf.set_default("chunk_size", 8192).unwrap();
+        conf.set_default("max_ranges", 16).unwrap();
 
         let root = std::env::current_dir();
         let root = root
@@ -36,8 +40,45 @@ lazy_static::lazy_static! {
     };
 }
 
+///Prefixes every line logged while a request is handled with its id,
+///so the lines of concurrent requests can be told apart.
+struct Tagged<L: Log>(L);
+
+impl<L: Log> Log for Tagged<L> {
+    fn enabled(&self, meta: &Metadata) -> bool { self.0.enabled(meta) }
+
+    fn log(&self, record: &Record) {
+        match request_id::current() {
+            Some(id) => self.0.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) { self.0.flush() }
+}
+
+fn init_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("SERV_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(Tagged(logger)))
+        .expect("failed to set the logger");
+}
+
 fn main() -> io::Result<()> {
-    pretty_env_logger::init_custom_env("SERV_LOG");
+    init_logger();
 
     debug!("initialized with config: \n{:#?}\n", *CONFIG);
 
@@ -27,6 +27,313 @@ pub struct Redirect {
     pub code:  u32,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Compression {
+    pub enabled:       bool,
+    pub precompressed: bool,
+    pub mime_types:    Vec<String>,
+    pub min_size:      usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled:       true,
+            precompressed: true,
+            mime_types:    vec![
+                "text/*".into(),
+                "application/javascript".into(),
+                "application/json".into(),
+                "application/xml".into(),
+                "image/svg+xml".into(),
+            ],
+            min_size:      256,
+        }
+    }
+}
+
+/// Upload sizes are in bytes. They limit what gets saved, while the
+/// top level `max_body_size` limits how much of a request gets read.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Uploads {
+    pub create_dirs:   bool,
+    pub max_file_size: u64,
+    pub max_files:     usize,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self {
+            create_dirs:   false,
+            max_file_size: 100 << 20,
+            max_files:     32,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Versioning {
+    pub enabled:      bool,
+    pub store:        PathBuf,
+    pub max_versions: usize,
+    pub max_age_days: u32,
+}
+
+impl Default for Versioning {
+    fn default() -> Self {
+        Self {
+            enabled:      false,
+            store:        ".versions".into(),
+            max_versions: 20,
+            max_age_days: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Warc {
+    pub record:   bool,
+    pub dir:      PathBuf,
+    pub prefix:   String,
+    pub max_size: u64,
+    pub replay:   Vec<PathBuf>,
+}
+
+impl Default for Warc {
+    fn default() -> Self {
+        Self {
+            record:   false,
+            dir:      "warc".into(),
+            prefix:   "ruserv".into(),
+            max_size: 1 << 30,
+            replay:   Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Deletes {
+    pub recursive: bool,
+    pub max_depth: usize,
+}
+
+impl Default for Deletes {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            max_depth: 16,
+        }
+    }
+}
+
+/// A limit of 0 means no limit.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DirQuota {
+    pub path:      PathBuf,
+    #[serde(default)]
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub max_files: u64,
+}
+
+/// A user of `*` applies to every user without a quota of their own.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserQuota {
+    pub user:      String,
+    #[serde(default)]
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub max_files: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Quotas {
+    pub dirs:   Vec<DirQuota>,
+    pub users:  Vec<UserQuota>,
+    pub ledger: PathBuf,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self {
+            dirs:   Vec::new(),
+            users:  Vec::new(),
+            ledger: "quotas.json".into(),
+        }
+    }
+}
+
+/// Sizes are in bytes and the rotation interval in seconds, where 0
+/// turns that kind of rotation off. `recent` is how many entries are
+/// kept in memory for `.well-known/access.log`. The format is one of
+/// `common`, `combined` or `json`, or else a format string of Apache
+/// style directives like `%h %u %t "%r" %>s %b %D`, where `%L` is
+/// the request id.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccessLog {
+    pub enabled:         bool,
+    pub file:            PathBuf,
+    pub format:          String,
+    pub max_size:        u64,
+    pub rotate_interval: u64,
+    pub keep:            usize,
+    pub compress:        bool,
+    pub recent:          usize,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self {
+            enabled:         false,
+            file:            "access.log".into(),
+            format:          "common".into(),
+            max_size:        64 << 20,
+            rotate_interval: 86400,
+            keep:            7,
+            compress:        true,
+            recent:          1000,
+        }
+    }
+}
+
+/// The recent entries of the access log are served at `path`, beneath
+/// the root, to users passing an auth file placed there, or to the
+/// admin. The admin password is given as its MD5 hex digest, the same
+/// way auth files store them.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LogViewer {
+    pub enabled:        bool,
+    pub path:           PathBuf,
+    pub admin_user:     String,
+    pub admin_password: String,
+}
+
+impl Default for LogViewer {
+    fn default() -> Self {
+        Self {
+            enabled:        true,
+            path:           ".well-known/access.log".into(),
+            admin_user:     String::new(),
+            admin_password: String::new(),
+        }
+    }
+}
+
+/// The audit log is queried at `path`, beneath the root. Readers are
+/// the users allowed to query it, with any authenticated user allowed
+/// when empty.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Audit {
+    pub enabled: bool,
+    pub file:    PathBuf,
+    pub path:    PathBuf,
+    pub readers: Vec<String>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file:    "audit.log".into(),
+            path:    ".well-known/audit.log".into(),
+            readers: Vec::new(),
+        }
+    }
+}
+
+/// Metrics are served at `path`, beneath the root, or only on `port`
+/// when one is given so they can be kept off the public port.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Metrics {
+    pub enabled: bool,
+    pub path:    PathBuf,
+    pub port:    Option<u32>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path:    "metrics".into(),
+            port:    None,
+        }
+    }
+}
+
+/// The status page is served at `path`, beneath the root, to requests
+/// passing an auth file placed there or coming from an allowed address.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ServerStatus {
+    pub enabled: bool,
+    pub path:    PathBuf,
+    pub allow:   Vec<IpAddr>,
+}
+
+impl Default for ServerStatus {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path:    "server-status".into(),
+            allow:   vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+        }
+    }
+}
+
+/// A command run after a PUT or DELETE of a path matching the glob.
+/// Globs are matched against the request path, where `*` and `?`
+/// stay within a directory and `**` crosses them.
+#[derive(Deserialize, Debug)]
+pub struct Hook {
+    pub glob:    String,
+    pub command: PathBuf,
+}
+
+/// The timeout is in milliseconds, after which hooks are killed.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Hooks {
+    pub commands: Vec<Hook>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub timeout:  Duration,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            timeout:  Duration::from_millis(30000),
+        }
+    }
+}
+
+/// Lock timeouts are in seconds.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Webdav {
+    pub lock_timeout:     u64,
+    pub max_lock_timeout: u64,
+}
+
+impl Default for Webdav {
+    fn default() -> Self {
+        Self {
+            lock_timeout:     600,
+            max_lock_timeout: 3600,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Settings {
     pub port: u32,
@@ -40,5 +347,34 @@ pub struct Settings {
     #[serde(deserialize_with = "deserialize_duration")]
     pub write_timeout: Duration,
     pub max_request_size: usize,
+    pub max_body_size: u64,
+    pub chunk_size: usize,
+    pub max_ranges: usize,
     pub auth: Auth,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub uploads: Uploads,
+    #[serde(default)]
+    pub deletes: Deletes,
+    #[serde(default)]
+    pub quotas: Quotas,
+    #[serde(default)]
+    pub versioning: Versioning,
+    #[serde(default)]
+    pub warc: Warc,
+    #[serde(default)]
+    pub webdav: Webdav,
+    #[serde(default)]
+    pub access_log: AccessLog,
+    #[serde(default)]
+    pub log_viewer: LogViewer,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub server_status: ServerStatus,
 }
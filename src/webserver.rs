@@ -1,6 +1,11 @@
 mod socket_handler;
 
+mod access_log;
+mod audit;
 mod clf;
+mod metrics;
+mod status;
+mod warc;
 pub mod requests;
 pub mod responses;
 pub mod shared;
@@ -30,6 +35,23 @@ impl WebServer {
         info!("bound to addr '{}' successfully", addr);
 
         listener.set_nonblocking(true)?;
+        status::start();
+
+        if CONFIG.metrics.enabled {
+            if let Some(port) = CONFIG.metrics.port {
+                let addr = format!("{}:{}", CONFIG.addr, port);
+                let metrics_listener = TcpListener::bind(&addr)?;
+                info!("serving metrics on '{}'", addr);
+
+                std::thread::spawn(move || metrics::serve(metrics_listener));
+            }
+        }
+
+        if warc::replaying() {
+            info!("serving from archives instead of '{}'", CONFIG.root.display());
+            warc::load_index();
+        }
+
         Ok(WebServer { listener })
     }
 
@@ -44,6 +66,7 @@ impl WebServer {
                     trace!("new connection received: '{}'", addr);
 
                     let handler = SocketHandler::new(stream)?;
+                    status::opened(addr);
 
                     let other_tx = t_tx.clone();
                     let handle = std::thread::spawn(move || {
@@ -54,6 +77,7 @@ impl WebServer {
                     });
 
                     conn_map.insert(addr, handle);
+                    metrics::connections(conn_map.len());
                 }
                 Err(err) => {
                     use io::ErrorKind;
@@ -74,6 +98,8 @@ impl WebServer {
                     let thread = conn_map.remove(&addr).expect(
                         "attempted to unwrap a connection that did not exist",
                     );
+                    metrics::connections(conn_map.len());
+                    status::closed(&addr);
 
                     match thread.join() {
                         Err(err) => error!("a thread panicked: '{:?}'", err),
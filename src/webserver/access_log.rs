@@ -0,0 +1,488 @@
+use super::clf::{LogEntry, LogFormat, COMMON};
+use super::requests::Request;
+use super::responses::*;
+use super::shared::*;
+use super::socket_handler::auth_handler::{AuthHandler, SuppliedAuth};
+use crate::settings::AccessLog;
+use crate::CONFIG;
+
+use chrono::{DateTime, Utc};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Result as ioResult, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+lazy_static::lazy_static! {
+    static ref RECENT: RwLock<VecDeque<LogEntry>> = {
+        RwLock::new(VecDeque::with_capacity(CONFIG.access_log.recent))
+    };
+    static ref FORMAT: LogFormat = {
+        CONFIG.access_log.format.parse().unwrap_or_else(|err| {
+            error!("falling back to the common log format: '{}'", err);
+            COMMON.parse().unwrap()
+        })
+    };
+    static ref WRITER: Mutex<Writer> = {
+        Mutex::new(Writer::new(&CONFIG.access_log))
+    };
+    static ref COMPRESSING: Mutex<()> = Mutex::new(());
+}
+
+///Appends entries to the access log file, rotating it once it grows
+///past the size limit or has been written to for longer than the
+///rotation interval. Rotated files are numbered from 1, the newest.
+struct Writer {
+    settings: AccessLog,
+    file:     Option<File>,
+    size:     u64,
+    opened:   SystemTime,
+}
+
+impl Writer {
+    fn new(settings: &AccessLog) -> Self {
+        Self {
+            settings: settings.clone(),
+            file:     None,
+            size:     0,
+            opened:   SystemTime::now(),
+        }
+    }
+
+    fn open(&mut self) -> ioResult<()> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.settings.file)?;
+        let meta = file.metadata()?;
+
+        //A log left from before a restart keeps aging from its creation.
+        self.size = meta.len();
+        self.opened = match meta.created() {
+            Ok(created) if self.size > 0 => created,
+            _ => SystemTime::now(),
+        };
+        self.file = Some(file);
+
+        Ok(())
+    }
+
+    fn rotation_due(&self, len: u64) -> bool {
+        let max_size = self.settings.max_size;
+        let interval = Duration::from_secs(self.settings.rotate_interval);
+        let age = self.opened.elapsed().unwrap_or_default();
+
+        self.size > 0
+            && ((max_size != 0 && self.size + len > max_size)
+                || (self.settings.rotate_interval != 0 && age >= interval))
+    }
+
+    ///Moves the full log out of the way. Compressed rotations are only
+    ///staged here, [`compress_staged`] numbers and compresses them later
+    ///so that writers don't wait on it. Returns whether one was staged.
+    fn rotate(&mut self) -> ioResult<bool> {
+        self.file = None;
+
+        let settings = &self.settings;
+        if settings.keep == 0 {
+            std::fs::remove_file(&settings.file)?;
+            return Ok(false);
+        }
+
+        if settings.compress {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            let mut stamp = now.as_nanos();
+            while staged(settings, stamp).exists() {
+                stamp += 1;
+            }
+
+            std::fs::rename(&settings.file, staged(settings, stamp))?;
+            return Ok(true);
+        }
+
+        shift(settings)?;
+        std::fs::rename(&settings.file, rotated(settings, 1))?;
+
+        Ok(false)
+    }
+
+    ///Appends a line, returning whether a rotation is waiting to be
+    ///compressed.
+    fn write(&mut self, line: &str) -> ioResult<bool> {
+        self.open()?;
+        let mut staged = false;
+        if self.rotation_due(line.len() as u64) {
+            debug!("rotating '{}'", self.settings.file.display());
+            staged = self.rotate()?;
+            self.open()?;
+        }
+
+        //Each entry goes out in a single write so lines never interleave.
+        let file = self.file.as_mut().unwrap();
+        file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(staged)
+    }
+}
+
+fn rotated(settings: &AccessLog, num: usize) -> PathBuf {
+    let mut name = settings.file.clone().into_os_string();
+    name.push(format!(".{}", num));
+    if settings.compress {
+        name.push(".gz");
+    }
+
+    name.into()
+}
+
+fn staged(settings: &AccessLog, stamp: u128) -> PathBuf {
+    let mut name = settings.file.clone().into_os_string();
+    name.push(format!(".rotating-{:039}", stamp));
+
+    name.into()
+}
+
+///Renumbers the rotated files to make room for a new first one,
+///dropping the oldest when there are as many as are kept.
+fn shift(settings: &AccessLog) -> ioResult<()> {
+    let keep = settings.keep;
+    if rotated(settings, keep).exists() {
+        std::fs::remove_file(rotated(settings, keep))?;
+    }
+    for num in (1..keep).rev() {
+        if rotated(settings, num).exists() {
+            std::fs::rename(
+                rotated(settings, num),
+                rotated(settings, num + 1),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+///Compresses the staged rotations into the numbered files, oldest
+///first. Runs apart from the writer, one pass at a time, since the
+///numbering can only be shifted by one pass.
+fn compress_staged(settings: &AccessLog) -> ioResult<()> {
+    let _guard = COMPRESSING.lock().unwrap();
+
+    let dir = match settings.file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut prefix = settings.file.file_name().unwrap_or_default().to_owned();
+    prefix.push(".rotating-");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let mut pending: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_name().to_string_lossy().starts_with(&prefix)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    pending.sort();
+
+    for path in pending {
+        shift(settings)?;
+
+        let mut current = File::open(&path)?;
+        let mut encoder = GzEncoder::new(
+            File::create(rotated(settings, 1))?,
+            Compression::default(),
+        );
+        std::io::copy(&mut current, &mut encoder)?;
+        encoder.finish()?;
+
+        std::fs::remove_file(&path)?;
+    }
+
+    Ok(())
+}
+
+///Writes an entry to the access log file, when enabled, and keeps it
+///among the recent entries, dropping the oldest once there are too
+///many.
+pub fn record(entry: LogEntry) {
+    if CONFIG.access_log.enabled {
+        let line = format!("{}\n", entry.format(&FORMAT));
+        let written = WRITER.lock().unwrap().write(&line);
+        match written {
+            Ok(true) => {
+                std::thread::spawn(|| {
+                    if let Err(err) = compress_staged(&CONFIG.access_log) {
+                        error!("failed to compress access log: '{}'", err);
+                    }
+                });
+            }
+            Ok(false) => {}
+            Err(err) => error!("failed to write access log: '{}'", err),
+        }
+    }
+
+    let max = CONFIG.access_log.recent;
+    if max == 0 {
+        return;
+    }
+
+    let mut recent = RECENT.write().unwrap();
+    while recent.len() >= max {
+        recent.pop_front();
+    }
+    recent.push_back(entry);
+}
+
+///The filters a view of the recent entries can use. The status is
+///either a code or a class like `4xx`, and the offset skips that many
+///of the newest matching entries.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub status: Option<String>,
+    pub path:   Option<String>,
+    pub ip:     Option<IpAddr>,
+    pub since:  Option<DateTime<Utc>>,
+    pub until:  Option<DateTime<Utc>>,
+    pub limit:  usize,
+    pub offset: usize,
+}
+
+impl Filter {
+    pub fn from_request(req: &Request) -> Result<Self, ()> {
+        let time = |name| match req.query_param(name) {
+            Some(time) => DateTime::parse_from_rfc3339(&time)
+                .map(|time| Some(time.with_timezone(&Utc)))
+                .map_err(|_| ()),
+            None => Ok(None),
+        };
+        let number = |name, default| match req.query_param(name) {
+            Some(num) => num.parse().map_err(|_| ()),
+            None => Ok(default),
+        };
+
+        let status = req.query_param("status").map(|val| val.to_lowercase());
+        let valid = status.as_ref().map_or(true, |status| {
+            status.len() == 3
+                && status.chars().all(|c| c == 'x' || c.is_ascii_digit())
+        });
+        if !valid {
+            return Err(());
+        }
+
+        let ip = match req.query_param("ip") {
+            Some(ip) => Some(ip.parse().map_err(|_| ())?),
+            None => None,
+        };
+
+        Ok(Self {
+            status,
+            path: req.query_param("path"),
+            ip,
+            since: time("since")?,
+            until: time("until")?,
+            limit: number("limit", 100)?,
+            offset: number("offset", 0)?,
+        })
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        let status = entry.status().to_string();
+
+        self.status.as_ref().map_or(true, |want| {
+            want.chars()
+                .zip(status.chars())
+                .all(|(want, got)| want == 'x' || want == got)
+        }) && self
+            .path
+            .as_ref()
+            .map_or(true, |path| entry.path().starts_with(path.as_str()))
+            && self.ip.map_or(true, |ip| entry.ip() == ip)
+            && self.since.map_or(true, |since| entry.time() >= since)
+            && self.until.map_or(true, |until| entry.time() < until)
+    }
+}
+
+///Whether the request carries the admin credential of the viewer.
+fn is_admin(req: &Request) -> bool {
+    let viewer = &CONFIG.log_viewer;
+    if viewer.admin_user.is_empty() {
+        return false;
+    }
+
+    let auth = match req.headers.authorization().map(|auth| auth.parse()) {
+        Some(Ok(SuppliedAuth::Basic { auth })) => auth,
+        _ => return false,
+    };
+    let decoded = base64::decode(&auth)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .unwrap_or_default();
+
+    let mut parts = decoded.splitn(2, ':');
+    let user = parts.next().unwrap_or_default();
+    let pass = parts.next().unwrap_or_default();
+
+    user == viewer.admin_user
+        && format!("{:x}", md5::compute(pass.as_bytes()))
+            == viewer.admin_password.to_lowercase()
+}
+
+///Serves the recent entries matching the filters of the request, as
+///text in the configured format or as JSON when the client asks for
+///it. Entries hold client addresses, so they're only served where an
+///auth file has checked the credentials of the request, or to the
+///admin.
+pub fn viewer_response(req: &Request, url: &Path) -> Response {
+    let protected = AuthHandler::new(url)
+        .map(|handler| handler.requires_auth())
+        .unwrap_or(false);
+
+    if !protected && !is_admin(req) {
+        warn!("refusing access log to '{}'", req.path.display());
+        if CONFIG.log_viewer.admin_user.is_empty() {
+            return Response::forbidden();
+        }
+
+        let mut headers = HeaderList::response_headers();
+        headers.resp_authenticate("Basic realm=\"access log\"".into());
+        return Response::unauthorized(headers);
+    }
+
+    let filter = match Filter::from_request(req) {
+        Ok(filter) => filter,
+        Err(()) => return Response::bad_request(),
+    };
+
+    let recent = RECENT.read().unwrap();
+    let mut entries: Vec<&LogEntry> = recent
+        .iter()
+        .rev()
+        .filter(|entry| filter.matches(entry))
+        .skip(filter.offset)
+        .take(filter.limit)
+        .collect();
+    entries.reverse();
+
+    let json = req
+        .headers
+        .get(ACCEPT)
+        .map_or(false, |accept| accept.contains("application/json"));
+    if json {
+        let entries: Vec<_> =
+            entries.iter().map(|entry| entry.to_json()).collect();
+        return Response::json_response(StatusCode::Ok, &entries);
+    }
+
+    let buff: Vec<u8> = entries
+        .iter()
+        .map(|entry| format!("{}\n", entry.format(&FORMAT)))
+        .collect::<String>()
+        .into();
+
+    let mut headers = HeaderList::response_headers();
+    headers.content(&mime::TEXT_PLAIN.to_string(), None, buff.len());
+
+    Response {
+        code: StatusCode::Ok,
+        headers,
+        data: Some(buff.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir()
+            .join(format!("access-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut writer = Writer::new(&AccessLog {
+            file: dir.join("access.log"),
+            max_size: 10,
+            rotate_interval: 0,
+            keep: 2,
+            ..Default::default()
+        });
+
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            writer.write(line).unwrap();
+        }
+        compress_staged(&writer.settings).unwrap();
+
+        let read = |name: &str| {
+            let mut buff = String::new();
+            GzDecoder::new(File::open(dir.join(name)).unwrap())
+                .read_to_string(&mut buff)
+                .unwrap();
+            buff
+        };
+
+        assert_eq!(
+            std::fs::read_to_string(dir.join("access.log")).unwrap(),
+            "fourth\n"
+        );
+        assert_eq!(read("access.log.1.gz"), "third\n");
+        assert_eq!(read("access.log.2.gz"), "second\n");
+        assert!(!dir.join("access.log.3.gz").exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter() {
+        let req: Request = "GET /docs/a.txt HTTP/1.1\r\nHost: \
+                            localhost\r\n\r\n"
+            .parse()
+            .unwrap();
+        let entry = LogEntry::new(
+            &"10.0.0.1:4000".parse().unwrap(),
+            &req,
+            &Response::not_found(),
+            None,
+        );
+
+        let filter = |query: &str| {
+            let req: Request = format!(
+                "GET /.well-known/access.log?{} HTTP/1.1\r\nHost: \
+                 localhost\r\n\r\n",
+                query
+            )
+            .parse()
+            .unwrap();
+            Filter::from_request(&req)
+        };
+
+        let matching = filter("status=4xx&path=/docs&ip=10.0.0.1").unwrap();
+        assert!(matching.matches(&entry));
+        assert_eq!(matching.limit, 100);
+
+        assert!(!filter("status=200").unwrap().matches(&entry));
+        assert!(!filter("ip=10.0.0.2").unwrap().matches(&entry));
+        assert!(!filter("until=2020-01-01T00:00:00Z")
+            .unwrap()
+            .matches(&entry));
+
+        assert!(filter("status=4").is_err());
+        assert!(filter("ip=nowhere").is_err());
+        assert!(filter("limit=-1").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use super::requests::Request;
+use super::responses::*;
+use super::shared::*;
+use super::socket_handler::auth_handler::AuthHandler;
+use super::socket_handler::etag::file_etag;
+use crate::CONFIG;
+
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref WRITER: Mutex<Option<File>> = Mutex::new(None);
+}
+
+///The most records a single query returns.
+const MAX_RESULTS: usize = 1000;
+
+///Whether a request can change what is stored, and so gets audited.
+pub fn is_mutating(method: &Method) -> bool {
+    match method {
+        Method::Get
+        | Method::Head
+        | Method::Options
+        | Method::Trace
+        | Method::Propfind => false,
+        _ => true,
+    }
+}
+
+fn etag_of(path: &Path) -> Option<String> {
+    if path.is_file() {
+        file_etag(path).ok()
+    } else {
+        None
+    }
+}
+
+///A single line of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub time:        DateTime<Utc>,
+    pub method:      String,
+    pub path:        String,
+    pub user:        Option<String>,
+    pub ip:          String,
+    pub size:        usize,
+    pub etag_before: Option<String>,
+    pub etag_after:  Option<String>,
+    pub status:      usize,
+}
+
+///A mutating request that has been seen but not answered yet, holding
+///on to the state of the file from before it was handled.
+pub struct Pending {
+    record: AuditRecord,
+    url:    std::path::PathBuf,
+}
+
+impl Pending {
+    pub fn begin(addr: &SocketAddr, req: &Request, url: &Path) -> Option<Self> {
+        if !CONFIG.audit.enabled || !is_mutating(&req.method) {
+            return None;
+        }
+
+        Some(Self {
+            record: AuditRecord {
+                time:        Utc::now(),
+                method:      req.method.to_string(),
+                path:        req.path.to_string_lossy().into(),
+                user:        None,
+                ip:          addr.ip().to_string(),
+                size:        req.payload.as_ref().map_or(0, |load| load.len()),
+                etag_before: etag_of(url),
+                etag_after:  None,
+                status:      0,
+            },
+            url:    url.into(),
+        })
+    }
+
+    ///Completes the record with the outcome, and the user whose
+    ///credentials an auth file checked, if any.
+    pub fn finish(mut self, resp: &Response, user: Option<String>) {
+        self.record.user = user;
+        self.record.etag_after = etag_of(&self.url);
+        self.record.status = resp.code.to_num();
+
+        if let Err(err) = append(&self.record) {
+            error!("failed to write audit record: '{}'", err);
+        }
+    }
+}
+
+fn append(record: &AuditRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let mut writer = WRITER.lock().unwrap();
+    if writer.is_none() {
+        *writer = Some(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&CONFIG.audit.file)?,
+        );
+    }
+
+    //Each record goes out in a single write so lines never interleave.
+    let file = writer.as_mut().unwrap();
+    file.write_all(&line)?;
+    file.flush()
+}
+
+///The filters a query of the audit log can use.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub user:   Option<String>,
+    pub path:   Option<String>,
+    pub method: Option<String>,
+    pub since:  Option<DateTime<Utc>>,
+    pub limit:  usize,
+}
+
+impl Query {
+    pub fn from_request(req: &Request) -> Result<Self, ()> {
+        let since = match req.query_param("since") {
+            Some(since) => Some(
+                DateTime::parse_from_rfc3339(&since)
+                    .map_err(|_| ())?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+
+        let limit = match req.query_param("limit") {
+            Some(limit) => limit.parse().map_err(|_| ())?,
+            None => 100,
+        };
+
+        Ok(Self {
+            user: req.query_param("user"),
+            path: req.query_param("path"),
+            method: req.query_param("method").map(|val| val.to_uppercase()),
+            since,
+            limit: usize::min(limit, MAX_RESULTS),
+        })
+    }
+
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.user
+            .as_ref()
+            .map_or(true, |user| record.user.as_ref() == Some(user))
+            && self
+                .path
+                .as_ref()
+                .map_or(true, |path| record.path.starts_with(path.as_str()))
+            && self
+                .method
+                .as_ref()
+                .map_or(true, |method| &record.method == method)
+            && self.since.map_or(true, |since| record.time >= since)
+    }
+}
+
+///Reads back the newest records matching the query, oldest first.
+pub fn search(query: &Query) -> std::io::Result<Vec<AuditRecord>> {
+    let file = match File::open(&CONFIG.audit.file) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut ret = std::collections::VecDeque::new();
+    for line in BufReader::new(file).lines() {
+        let record: AuditRecord = match serde_json::from_str(&line?) {
+            Ok(record) => record,
+            Err(err) => {
+                warn!("skipping bad audit record: '{}'", err);
+                continue;
+            }
+        };
+
+        if query.matches(&record) {
+            ret.push_back(record);
+            if ret.len() > query.limit {
+                ret.pop_front();
+            }
+        }
+    }
+
+    Ok(ret.into())
+}
+
+///Answers a query of the audit log. The log holds who changed what,
+///so it is only served where an auth file has checked the credentials
+///of the request, and then only to the configured readers.
+pub fn audit_response(req: &Request, url: &Path) -> Response {
+    if !CONFIG.audit.enabled {
+        return Response::not_found();
+    }
+
+    let protected = AuthHandler::new(url)
+        .map(|handler| handler.requires_auth())
+        .unwrap_or(false);
+    let user = AuthHandler::supplied_user(req);
+    let allowed = match user {
+        Some(ref user) => {
+            CONFIG.audit.readers.is_empty()
+                || CONFIG.audit.readers.contains(user)
+        }
+        None => false,
+    };
+
+    if !protected || !allowed {
+        warn!("refusing audit log to '{}'", user.unwrap_or_default());
+        return Response::forbidden();
+    }
+
+    let query = match Query::from_request(req) {
+        Ok(query) => query,
+        Err(()) => return Response::bad_request(),
+    };
+
+    match search(&query) {
+        Ok(records) => Response::json_response(StatusCode::Ok, &records),
+        Err(err) => {
+            error!("failed to read audit log: '{}'", err);
+            Response::internal_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_matches() {
+        let record = AuditRecord {
+            time:        "2020-01-02T03:04:05Z".parse().unwrap(),
+            method:      "PUT".into(),
+            path:        "/docs/a.txt".into(),
+            user:        Some("mln".into()),
+            ip:          "127.0.0.1".into(),
+            size:        10,
+            etag_before: None,
+            etag_after:  Some("\"abc\"".into()),
+            status:      201,
+        };
+
+        let req: Request = "GET /.well-known/audit.log?user=mln&path=/docs&\
+                            method=put HTTP/1.1\r\nHost: localhost\r\n\r\n"
+            .parse()
+            .unwrap();
+        let query = Query::from_request(&req).unwrap();
+        assert!(query.matches(&record));
+        assert_eq!(query.limit, 100);
+
+        let query = Query {
+            since: Some("2020-01-03T00:00:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&record));
+
+        let query = Query {
+            user: Some("bda".into()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&record));
+
+        let req: Request = "GET /.well-known/audit.log?since=yesterday \
+                            HTTP/1.1\r\nHost: localhost\r\n\r\n"
+            .parse()
+            .unwrap();
+        assert!(Query::from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_is_mutating() {
+        assert!(is_mutating(&Method::Put));
+        assert!(is_mutating(&Method::Patch));
+        assert!(is_mutating(&Method::Move));
+        assert!(!is_mutating(&Method::Get));
+        assert!(!is_mutating(&Method::Propfind));
+    }
+}
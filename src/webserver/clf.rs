@@ -1,62 +1,392 @@
 use super::requests::*;
 use super::responses::*;
 use super::shared::headers::*;
+use super::shared::request_id;
 use chrono::{DateTime, Utc};
-use std::net::SocketAddr;
+use serde_json::json;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+pub const COMBINED: &str =
+    "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+lazy_static::lazy_static! {
+    static ref COMMON_FORMAT: LogFormat = COMMON.parse().unwrap();
+}
 
 pub struct LogEntry {
     client_addr:  SocketAddr,
     client_ident: Option<String>,
     userid:       Option<String>,
     time:         DateTime<Utc>,
-    req_line:     String,
+    method:       String,
+    path:         String,
+    query:        String,
+    protocol:     String,
     status_code:  StatusCode,
-    sent_size:    usize,
+    sent_size:    u64,
+    duration:     Duration,
+    referer:      Option<String>,
+    user_agent:   Option<String>,
+    request_id:   Option<String>,
 }
 
 impl LogEntry {
-    pub fn new(addr: &SocketAddr, req: &Request, resp: &Response) -> Self {
-        let req_line =
-            format!("{} {} {}", req.method, req.path.display(), req.ver);
-
-        let cont_len = resp
-            .headers
-            .get(CONTENT_LENGTH)
-            .clone()
-            .unwrap_or(&String::from("0"))
-            .parse()
-            .unwrap_or(0);
+    ///Starts an entry for a response about to be written, where the
+    ///user is the one who authenticated, if any.
+    pub fn new(
+        addr: &SocketAddr,
+        req: &Request,
+        resp: &Response,
+        userid: Option<String>,
+    ) -> Self {
+        let header = |name| req.headers.get(name).map(String::from);
 
         Self {
-            client_addr:  addr.clone(),
+            client_addr: *addr,
             client_ident: None,
-            userid:       None,
-            time:         Utc::now(),
-            req_line:     req_line,
-            status_code:  resp.code.clone(),
-            sent_size:    cont_len,
+            userid,
+            time: Utc::now(),
+            method: req.method.to_string(),
+            path: req.path.display().to_string(),
+            query: req.query.clone(),
+            protocol: req.ver.clone(),
+            status_code: resp.code.clone(),
+            sent_size: 0,
+            duration: Duration::default(),
+            referer: header(REFERER),
+            user_agent: header(USER_AGENT),
+            request_id: request_id::current(),
+        }
+    }
+
+    ///Fills in what's only known once the response has been written.
+    pub fn finish(&mut self, sent_size: u64, duration: Duration) {
+        self.sent_size = sent_size;
+        self.duration = duration;
+    }
+
+    pub fn ip(&self) -> IpAddr { self.client_addr.ip() }
+
+    pub fn time(&self) -> DateTime<Utc> { self.time }
+
+    pub fn path(&self) -> &str { &self.path }
+
+    pub fn status(&self) -> usize { self.status_code.to_num() }
+
+    fn req_line(&self) -> String {
+        if self.query.is_empty() {
+            format!("{} {} {}", self.method, self.path, self.protocol)
+        } else {
+            format!(
+                "{} {}?{} {}",
+                self.method, self.path, self.query, self.protocol
+            )
+        }
+    }
+
+    fn field(&self, field: Field) -> String {
+        use Field::*;
+
+        let or_dash =
+            |val: &Option<String>| val.as_deref().map_or("-".into(), escape);
+
+        match field {
+            RemoteHost => self.client_addr.ip().to_string(),
+            Ident => or_dash(&self.client_ident),
+            User => or_dash(&self.userid),
+            Time => format!("[{}]", self.time.format("%d/%h/%Y:%T %z")),
+            RequestLine => escape(&self.req_line()),
+            Method => self.method.clone(),
+            Path => escape(&self.path),
+            Query if self.query.is_empty() => String::new(),
+            Query => format!("?{}", escape(&self.query)),
+            Protocol => self.protocol.clone(),
+            Status => self.status_code.to_num().to_string(),
+            BodySize if self.sent_size == 0 => "-".into(),
+            BodySize | Bytes => self.sent_size.to_string(),
+            Micros => self.duration.as_micros().to_string(),
+            Seconds => self.duration.as_secs().to_string(),
+            Referer => or_dash(&self.referer),
+            UserAgent => or_dash(&self.user_agent),
+            RequestId => or_dash(&self.request_id),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "time":        self.time.to_rfc3339(),
+            "remote_addr": self.client_addr.ip().to_string(),
+            "user":        self.userid,
+            "method":      self.method,
+            "path":        self.path,
+            "query":       Some(&self.query).filter(|query| !query.is_empty()),
+            "protocol":    self.protocol,
+            "status":      self.status_code.to_num(),
+            "bytes":       self.sent_size,
+            "duration_us": self.duration.as_micros() as u64,
+            "referer":     self.referer,
+            "user_agent":  self.user_agent,
+            "request_id":  self.request_id,
+        })
+    }
+
+    pub fn format(&self, format: &LogFormat) -> String {
+        match format {
+            LogFormat::Json => self.to_json().to_string(),
+            LogFormat::Pattern(tokens) => tokens
+                .iter()
+                .map(|token| match token {
+                    Token::Literal(lit) => lit.clone(),
+                    Token::Field(field) => self.field(*field),
+                })
+                .collect(),
+        }
+    }
+}
+
+///Escapes quotes, backslashes and control characters in values taken
+///from the request, so they can't forge or break up entries.
+fn escape(val: &str) -> String {
+    let mut ret = String::with_capacity(val.len());
+    for c in val.chars() {
+        match c {
+            '"' | '\\' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            c if c.is_control() => {
+                ret.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => ret.push(c),
         }
     }
+
+    ret
 }
 
 use std::fmt::{Display, Formatter, Result as fmtResult};
 impl Display for LogEntry {
     fn fmt(&self, fmt: &mut Formatter) -> fmtResult {
-        let ident = self.client_ident.clone().unwrap_or("-".into());
-        let usrid = self.userid.clone().unwrap_or("-".into());
-
-        let date_form = self.time.format("%d/%h/%Y:%T %z");
-
-        write!(
-            fmt,
-            "{} {} {} [{}] \"{}\" {} {}",
-            self.client_addr.ip(),
-            ident,
-            usrid,
-            date_form,
-            self.req_line,
-            self.status_code.to_num(),
-            self.sent_size
-        )
+        write!(fmt, "{}", self.format(&COMMON_FORMAT))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    RemoteHost,
+    Ident,
+    User,
+    Time,
+    RequestLine,
+    Method,
+    Path,
+    Query,
+    Protocol,
+    Status,
+    BodySize,
+    Bytes,
+    Micros,
+    Seconds,
+    Referer,
+    UserAgent,
+    RequestId,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Literal(String),
+    Field(Field),
+}
+
+///How entries are written: one JSON object per line, or a format
+///string made of Apache style `%` directives.
+#[derive(Debug, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Pattern(Vec<Token>),
+}
+
+#[derive(Debug)]
+pub enum LogFormatError {
+    UnknownDirective(String),
+    Unterminated(String),
+}
+
+impl Display for LogFormatError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmtResult {
+        use LogFormatError::*;
+
+        match self {
+            UnknownDirective(dir) => {
+                write!(fmt, "unknown log format directive '{}'", dir)
+            }
+            Unterminated(dir) => {
+                write!(fmt, "unterminated log format directive '{}'", dir)
+            }
+        }
+    }
+}
+
+impl Error for LogFormatError {}
+
+impl FromStr for LogFormat {
+    type Err = LogFormatError;
+
+    ///Takes the names `common`, `combined` and `json`, or a format
+    ///string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "common" => COMMON.parse(),
+            "combined" => COMBINED.parse(),
+            "json" => Ok(LogFormat::Json),
+            _ => parse_pattern(s),
+        }
+    }
+}
+
+fn parse_pattern(s: &str) -> Result<LogFormat, LogFormatError> {
+    use Field::*;
+    use LogFormatError::*;
+
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+
+        let mut directive = String::from("%");
+        let mut next = || {
+            let c = chars.next();
+            if let Some(c) = c {
+                directive.push(c);
+            }
+            c
+        };
+
+        let mut c = next();
+        let mut name = None;
+        if c == Some('{') {
+            let mut buff = String::new();
+            loop {
+                match next() {
+                    Some('}') => break,
+                    Some(c) => buff.push(c),
+                    None => return Err(Unterminated(directive)),
+                }
+            }
+            name = Some(buff.to_lowercase());
+            c = next();
+        }
+        //The original and final status are the same thing here.
+        if c == Some('>') || c == Some('<') {
+            c = next();
+        }
+
+        let field = match (c, name.as_deref()) {
+            (Some('%'), None) => {
+                literal.push('%');
+                continue;
+            }
+            (Some('h'), None) | (Some('a'), None) => RemoteHost,
+            (Some('l'), None) => Ident,
+            (Some('u'), None) => User,
+            (Some('t'), None) => Time,
+            (Some('r'), None) => RequestLine,
+            (Some('m'), None) => Method,
+            (Some('U'), None) => Path,
+            (Some('q'), None) => Query,
+            (Some('H'), None) => Protocol,
+            (Some('s'), None) => Status,
+            (Some('b'), None) => BodySize,
+            (Some('B'), None) => Bytes,
+            (Some('D'), None) => Micros,
+            (Some('T'), None) => Seconds,
+            (Some('L'), None) => RequestId,
+            (Some('i'), Some(REFERER)) => Referer,
+            (Some('i'), Some(USER_AGENT)) => UserAgent,
+            (None, _) => return Err(Unterminated(directive)),
+            _ => return Err(UnknownDirective(directive)),
+        };
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+        tokens.push(Token::Field(field));
+    }
+
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(LogFormat::Pattern(tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> LogEntry {
+        let req: Request = "GET /a%20b.txt?x=1 HTTP/1.1\r\nHost: \
+                            localhost\r\nUser-Agent: curl/7.0 \
+                            \"quoted\"\r\n\r\n"
+            .parse()
+            .unwrap();
+
+        let mut entry = LogEntry::new(
+            &"127.0.0.1:4000".parse().unwrap(),
+            &req,
+            &Response::no_content(),
+            Some("mln".into()),
+        );
+        entry.time = "2020-01-02T03:04:05Z".parse().unwrap();
+        entry.request_id = Some("abc-123".into());
+        entry.finish(1234, Duration::from_micros(2500));
+        entry
+    }
+
+    #[test]
+    fn test_formats() {
+        let entry = entry();
+
+        assert_eq!(
+            entry.to_string(),
+            "127.0.0.1 - mln [02/Jan/2020:03:04:05 +0000] \"GET \
+             /a%20b.txt?x=1 HTTP/1.1\" 204 1234"
+        );
+
+        let combined: LogFormat = "combined".parse().unwrap();
+        assert!(entry
+            .format(&combined)
+            .ends_with("204 1234 \"-\" \"curl/7.0 \\\"quoted\\\"\""));
+
+        let custom: LogFormat =
+            "%m %U%q %>s %D%% %{referer}i %L".parse().unwrap();
+        assert_eq!(
+            entry.format(&custom),
+            "GET /a%20b.txt?x=1 204 2500% - abc-123"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.format(&LogFormat::Json)).unwrap();
+        assert_eq!(json["user"], "mln");
+        assert_eq!(json["bytes"], 1234);
+        assert_eq!(json["duration_us"], 2500);
+        assert_eq!(json["referer"], serde_json::Value::Null);
+        assert_eq!(json["request_id"], "abc-123");
+    }
+
+    #[test]
+    fn test_bad_formats() {
+        for bad in &["%z", "%{Referer", "%{Cookie}i", "trailing %"] {
+            assert!(bad.parse::<LogFormat>().is_err(), "{}", bad);
+        }
     }
 }
@@ -0,0 +1,343 @@
+use super::requests::Request;
+use super::responses::*;
+use super::shared::*;
+use crate::CONFIG;
+
+use log::*;
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Result as ioResult};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+///The upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const CLASSES: &[&str] = &["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+static METHODS: &[Method] = &[
+    Method::Get,
+    Method::Head,
+    Method::Options,
+    Method::Trace,
+    Method::Put,
+    Method::Post,
+    Method::Delete,
+    Method::Patch,
+    Method::Propfind,
+    Method::Proppatch,
+    Method::Mkcol,
+    Method::Copy,
+    Method::Move,
+    Method::Lock,
+    Method::Unlock,
+];
+
+///How a request for a path without an exact file was negotiated.
+#[derive(Debug, Clone, Copy)]
+pub enum Negotiation {
+    Chosen,
+    MultipleChoices,
+    NotAcceptable,
+    NoMatch,
+}
+
+///The label of each outcome, in the order of `Negotiation`.
+const NEGOTIATIONS: &[&str] =
+    &["chosen", "multiple_choices", "not_acceptable", "no_match"];
+
+fn counters(len: usize) -> Vec<AtomicU64> {
+    (0..len).map(|_| AtomicU64::new(0)).collect()
+}
+
+///Every counter is atomic, so connections update them without taking
+///any locks.
+struct Counters {
+    requests:       Vec<AtomicU64>,
+    latency:        Vec<AtomicU64>,
+    latency_sum:    AtomicU64,
+    bytes_sent:     AtomicU64,
+    bytes_received: AtomicU64,
+    connections:    AtomicU64,
+    cgi_runs:       AtomicU64,
+    cgi_failures:   AtomicU64,
+    auth_failures:  AtomicU64,
+    negotiations:   Vec<AtomicU64>,
+}
+
+lazy_static::lazy_static! {
+    static ref COUNTERS: Counters = Counters {
+        requests:       counters(METHODS.len() * CLASSES.len()),
+        latency:        counters(BUCKETS.len() + 1),
+        latency_sum:    AtomicU64::new(0),
+        bytes_sent:     AtomicU64::new(0),
+        bytes_received: AtomicU64::new(0),
+        connections:    AtomicU64::new(0),
+        cgi_runs:       AtomicU64::new(0),
+        cgi_failures:   AtomicU64::new(0),
+        auth_failures:  AtomicU64::new(0),
+        negotiations:   counters(NEGOTIATIONS.len()),
+    };
+}
+
+fn add(counter: &AtomicU64, val: u64) {
+    counter.fetch_add(val, Ordering::Relaxed);
+}
+
+fn get(counter: &AtomicU64) -> u64 { counter.load(Ordering::Relaxed) }
+
+///The histogram bucket a request taking this long falls in, where the
+///last is the `+Inf` bucket.
+fn bucket(duration: Duration) -> usize {
+    let secs = duration.as_secs_f64();
+
+    BUCKETS
+        .iter()
+        .position(|bound| secs <= *bound)
+        .unwrap_or(BUCKETS.len())
+}
+
+///Counts a request once its response has been written.
+pub fn request(method: &Method, status: usize, duration: Duration, sent: u64) {
+    let method = METHODS.iter().position(|known| known == method);
+    let class = (status / 100).checked_sub(1).filter(|class| *class < 5);
+
+    if let (Some(method), Some(class)) = (method, class) {
+        add(&COUNTERS.requests[method * CLASSES.len() + class], 1);
+    }
+
+    add(&COUNTERS.latency[bucket(duration)], 1);
+    add(&COUNTERS.latency_sum, duration.as_micros() as u64);
+    add(&COUNTERS.bytes_sent, sent);
+}
+
+pub fn received(bytes: u64) { add(&COUNTERS.bytes_received, bytes); }
+
+pub fn connections(open: usize) {
+    COUNTERS.connections.store(open as u64, Ordering::Relaxed);
+}
+
+pub fn cgi_run(failed: bool) {
+    add(&COUNTERS.cgi_runs, 1);
+    if failed {
+        add(&COUNTERS.cgi_failures, 1);
+    }
+}
+
+pub fn auth_failed() { add(&COUNTERS.auth_failures, 1); }
+
+pub fn negotiated(outcome: Negotiation) {
+    add(&COUNTERS.negotiations[outcome as usize], 1);
+}
+
+fn header(buff: &mut String, name: &str, typ: &str, help: &str) {
+    let _ = writeln!(buff, "# HELP {} {}", name, help);
+    let _ = writeln!(buff, "# TYPE {} {}", name, typ);
+}
+
+fn single(buff: &mut String, name: &str, typ: &str, help: &str, val: u64) {
+    header(buff, name, typ, help);
+    let _ = writeln!(buff, "{} {}", name, val);
+}
+
+///Renders every metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut buff = String::new();
+
+    header(
+        &mut buff,
+        "ruserv_requests_total",
+        "counter",
+        "Requests answered, by method and status class.",
+    );
+    for (i, method) in METHODS.iter().enumerate() {
+        for (j, class) in CLASSES.iter().enumerate() {
+            let _ = writeln!(
+                buff,
+                "ruserv_requests_total{{method=\"{}\",class=\"{}\"}} {}",
+                method,
+                class,
+                get(&COUNTERS.requests[i * CLASSES.len() + j])
+            );
+        }
+    }
+
+    header(
+        &mut buff,
+        "ruserv_request_duration_seconds",
+        "histogram",
+        "Time from reading a request to writing its response.",
+    );
+    let mut count = 0;
+    for (i, counter) in COUNTERS.latency.iter().enumerate() {
+        count += get(counter);
+        let bound = BUCKETS
+            .get(i)
+            .map_or("+Inf".into(), |bound| bound.to_string());
+        let _ = writeln!(
+            buff,
+            "ruserv_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+            bound, count
+        );
+    }
+    let _ = writeln!(
+        buff,
+        "ruserv_request_duration_seconds_sum {}",
+        get(&COUNTERS.latency_sum) as f64 / 1e6
+    );
+    let _ = writeln!(buff, "ruserv_request_duration_seconds_count {}", count);
+
+    single(
+        &mut buff,
+        "ruserv_sent_bytes_total",
+        "counter",
+        "Bytes of response bodies written.",
+        get(&COUNTERS.bytes_sent),
+    );
+    single(
+        &mut buff,
+        "ruserv_received_bytes_total",
+        "counter",
+        "Bytes of requests read, with their payloads.",
+        get(&COUNTERS.bytes_received),
+    );
+    single(
+        &mut buff,
+        "ruserv_connections",
+        "gauge",
+        "Connections currently open.",
+        get(&COUNTERS.connections),
+    );
+    single(
+        &mut buff,
+        "ruserv_cgi_runs_total",
+        "counter",
+        "CGI scripts run.",
+        get(&COUNTERS.cgi_runs),
+    );
+    single(
+        &mut buff,
+        "ruserv_cgi_failures_total",
+        "counter",
+        "CGI scripts that failed to produce a response.",
+        get(&COUNTERS.cgi_failures),
+    );
+    single(
+        &mut buff,
+        "ruserv_auth_failures_total",
+        "counter",
+        "Requests refused by an auth file.",
+        get(&COUNTERS.auth_failures),
+    );
+
+    header(
+        &mut buff,
+        "ruserv_negotiations_total",
+        "counter",
+        "Content negotiations, by outcome.",
+    );
+    for (i, name) in NEGOTIATIONS.iter().enumerate() {
+        let _ = writeln!(
+            buff,
+            "ruserv_negotiations_total{{outcome=\"{}\"}} {}",
+            name,
+            get(&COUNTERS.negotiations[i])
+        );
+    }
+
+    buff
+}
+
+pub fn metrics_response() -> Response {
+    let buff: Vec<u8> = render().into();
+
+    let mut headers = HeaderList::response_headers();
+    headers.content("text/plain; version=0.0.4", None, buff.len());
+
+    Response {
+        code: StatusCode::Ok,
+        headers,
+        data: Some(buff.into()),
+    }
+}
+
+///Answers requests for the metrics on their own port, one request per
+///connection.
+pub fn serve(listener: TcpListener) {
+    for stream in listener.incoming() {
+        let res = stream.and_then(answer);
+        if let Err(err) = res {
+            warn!("failed to answer metrics request: '{}'", err);
+        }
+    }
+}
+
+fn answer(mut stream: TcpStream) -> ioResult<()> {
+    stream.set_read_timeout(Some(CONFIG.read_timeout))?;
+    stream.set_write_timeout(Some(CONFIG.write_timeout))?;
+
+    let mut buff = Vec::new();
+    let mut in_buff = [0; 1024];
+    while !buff.windows(4).any(|win| win == b"\r\n\r\n") {
+        let siz = stream.read(&mut in_buff)?;
+        if siz == 0 || buff.len() > 8192 {
+            return Ok(());
+        }
+        buff.extend_from_slice(&in_buff[..siz]);
+    }
+
+    let path = Path::new("/").join(&CONFIG.metrics.path);
+    let mut resp = match String::from_utf8_lossy(&buff).parse::<Request>() {
+        Ok(ref req) if req.method == Method::Get && req.path == path => {
+            metrics_response()
+        }
+        Ok(_) => Response::not_found(),
+        Err(_) => Response::bad_request(),
+    };
+
+    resp.headers.connection(connection::CLOSE);
+    if resp.headers.is_chunked() {
+        resp.write_chunked(&mut stream)?;
+    } else {
+        resp.write_self(&mut stream)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(Duration::from_millis(1)), 0);
+        assert_eq!(bucket(Duration::from_millis(10)), 1);
+        assert_eq!(bucket(Duration::from_millis(300)), 6);
+        assert_eq!(bucket(Duration::from_secs(60)), BUCKETS.len());
+    }
+
+    #[test]
+    fn test_render() {
+        request(&Method::Mkcol, 507, Duration::from_millis(20), 100);
+        request(&Method::Mkcol, 507, Duration::from_secs(20), 0);
+        negotiated(Negotiation::NotAcceptable);
+
+        let text = render();
+        assert!(text.contains(
+            "ruserv_requests_total{method=\"MKCOL\",class=\"5xx\"} 2\n"
+        ));
+        assert!(text.contains(
+            "ruserv_negotiations_total{outcome=\"not_acceptable\"} 1\n"
+        ));
+        assert!(
+            text.contains("# TYPE ruserv_request_duration_seconds histogram")
+        );
+        assert!(text.lines().any(|line| line.starts_with(
+            "ruserv_request_duration_seconds_bucket{le=\"+Inf\"}"
+        )));
+    }
+}
@@ -48,6 +48,14 @@ impl Request {
     pub fn set_payload(&mut self, payload: Vec<u8>) {
         self.payload = Some(payload);
     }
+
+    /// Looks up a parameter in the query string, a parameter given
+    /// without a value comes back as an empty string.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, val)| val.into_owned())
+    }
 }
 
 impl Display for RequestParsingError {
@@ -183,4 +191,15 @@ mod test {
         let request: Result<Request, _> = request_str.parse();
         assert!(request.is_ok());
     }
+
+    #[test]
+    fn query_param_test() {
+        let request_str =
+            "GET /file.txt?versions&version=12%203 HTTP/1.1\r\nHost: a\r\n\r\n";
+
+        let request: Request = request_str.parse().unwrap();
+        assert_eq!(request.query_param("versions"), Some(String::new()));
+        assert_eq!(request.query_param("version"), Some("12 3".into()));
+        assert_eq!(request.query_param("other"), None);
+    }
 }
@@ -1,10 +1,14 @@
 mod content_negotiator;
 mod templates;
 mod cgi_handler;
+mod compression;
+mod chunked;
 
-use content_negotiator::*;
+pub use content_negotiator::ContentNegotiator;
 use templates::*;
 use cgi_handler::*;
+use compression::*;
+use chunked::*;
 
 use super::redirect::*;
 use super::status_code::StatusCode;
@@ -13,12 +17,15 @@ use crate::webserver::shared::*;
 use crate::webserver::socket_handler::etag::*;
 use crate::CONFIG;
 use crate::webserver::socket_handler::auth_handler::*;
+use crate::webserver::socket_handler::patch::PATCH_TYPES;
+use crate::webserver::metrics::{self, Negotiation};
 
 use std::io::Result as ioResult;
 use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
 use std::net::SocketAddr;
 
+use chrono::Utc;
 use log::*;
 use mime::Mime;
 use tera::Tera;
@@ -26,13 +33,11 @@ use tera::Tera;
 lazy_static::lazy_static! {
     static ref TERA: Tera = {
         use crate::CONFIG;
-        use tera::compile_templates;
-
         lazy_static::initialize(&CONFIG);
 
-        compile_templates!(&CONFIG.templates
+        Tera::new(&CONFIG.templates
             .display()
-            .to_string())
+            .to_string()).expect("templates")
     };
 
     static ref DEFAULT_LANGUAGE: String = {
@@ -80,7 +85,7 @@ pub struct Response {
 impl Response {
     pub fn error(code: StatusCode, desc: &str, mut headers: HeaderList) -> Self {
         let holder = ErrorTemplate::new(code.clone(), desc);
-        let data = TERA.render("error.html", &holder);
+        let data = TERA.render("error.html", &tera::Context::from_serialize(&holder).unwrap());
 
         match data {
             Ok(string) => {
@@ -140,12 +145,15 @@ impl Response {
         )
     }
 
-    pub fn range_not_satisfiable() -> Self {
+    pub fn range_not_satisfiable(len: u64) -> Self {
+        let mut headers = HeaderList::response_headers();
+        headers.content_range(None, len);
+
         Response::error(
             StatusCode::RangeNotSatisfiable,
             "The given range was outside of the bounds of the requested \
              entity.",
-            HeaderList::response_headers(),
+            headers,
         )
     }
 
@@ -176,6 +184,17 @@ impl Response {
         )
     }
 
+    pub fn payload_too_large() -> Self {
+        let mut headers = HeaderList::response_headers();
+        headers.connection(connection::CLOSE.into());
+
+        Response::error(
+            StatusCode::PayloadTooLarge,
+            "The request body is too large.",
+            headers,
+        )
+    }
+
     pub fn multiple_choices(headers: HeaderList) -> Self {
         Response::error(
             StatusCode::MultipleChoice,
@@ -216,6 +235,22 @@ impl Response {
         )
     }
 
+    pub fn no_content() -> Self {
+        Response {
+            code:    StatusCode::NoContent,
+            headers: HeaderList::response_headers(),
+            data:    None,
+        }
+    }
+
+    pub fn insufficient_storage(err: &dyn std::fmt::Display) -> Self {
+        Response::error(
+            StatusCode::InsufficientStorage,
+            &format!("Not enough storage is left: {}.", err),
+            HeaderList::response_headers(),
+        )
+    }
+
     pub fn precondition_failed() -> Self {
         Response::error(
             StatusCode::PreconditionFailed,
@@ -224,6 +259,14 @@ impl Response {
         )
     }
 
+    pub fn conflict(desc: &str) -> Self {
+        Response::error(
+            StatusCode::Conflict,
+            desc,
+            HeaderList::response_headers(),
+        )
+    }
+
     pub fn not_acceptable() -> Self {
         Response::error(
             StatusCode::NotAcceptable,
@@ -236,7 +279,12 @@ impl Response {
         let mut headers = HeaderList::response_headers();
         match AuthHandler::new(path) {
             Ok(handler) => {
-                headers.allow(&handler.allows());
+                let allows = handler.allows();
+                if allows.contains(&Method::Patch) {
+                    headers.accept_patch(PATCH_TYPES);
+                }
+                headers.allow(&allows);
+                headers.dav();
                 Self {
                     code: StatusCode::Ok,
                     data: None,
@@ -317,6 +365,7 @@ impl Response {
 
                 let desc = map_file(path);
                 headers.content_language(&desc.lang);
+                headers.accept_ranges();
 
                 if let Some(enc) = desc.enc {
                     headers.content_encoding(&enc);
@@ -343,65 +392,240 @@ impl Response {
         }
     }
 
+    /// Serves the file at `stored` in place of the one at `path`,
+    /// keeping the type and language of the latter.
+    pub fn file_response_as(path: &Path, stored: &Path) -> Self {
+        let mut resp = Self::file_response(stored);
+
+        if resp.code == StatusCode::Ok {
+            let desc = map_file(path);
+            let len = resp
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|len| len.parse().ok())
+                .unwrap_or(0);
+
+            resp.headers.content(&desc.typ.to_string(), desc.charset, len);
+            resp.headers.content_language(&desc.lang);
+
+            match desc.enc {
+                Some(enc) => resp.headers.content_encoding(&enc),
+                None => resp.headers.remove(CONTENT_ENCODING),
+            }
+        }
+
+        resp
+    }
+
+    /// Serves a precompressed copy of a file in its place.
+    fn precompressed_response(
+        path: &Path,
+        sibling: &Path,
+        coding: &str,
+    ) -> Self {
+        let mut resp = Self::file_response_as(path, sibling);
+
+        if resp.code == StatusCode::Ok {
+            resp.headers.content_encoding(coding);
+
+            //Tag the sibling as a variant of the original so it
+            //revalidates against the original's etag.
+            if let Ok(etag) = file_etag(path) {
+                resp.headers.etag(&encoded_etag(&etag, coding));
+            }
+        }
+
+        resp
+    }
+
+    /// Renders the given value with an HTML template.
+    pub fn template_response<T: serde::Serialize>(
+        code: StatusCode,
+        template: &str,
+        val: &T
+    ) -> Self {
+        let data = TERA.render(template, &tera::Context::from_serialize(val).unwrap());
+
+        match data {
+            Ok(string) => {
+                let data: Vec<_> = string.into();
+                let mut headers = HeaderList::response_headers();
+                headers.content("text/html", None, data.len());
+
+                Self {
+                    code,
+                    headers,
+                    data: Some(data.into()),
+                }
+            }
+            Err(err) => {
+                error!("failed to render template '{}': '{}'", template, err);
+                Response::internal_error()
+            }
+        }
+    }
+
+    pub fn json_response<T: serde::Serialize>(code: StatusCode, val: &T) -> Self {
+        match serde_json::to_vec_pretty(val) {
+            Ok(data) => {
+                let mut headers = HeaderList::response_headers();
+                headers.content("application/json", None, data.len());
+
+                Self {
+                    code,
+                    headers,
+                    data: Some(data.into()),
+                }
+            }
+            Err(err) => {
+                error!("failed to serialize JSON response: '{}'", err);
+                Response::internal_error()
+            }
+        }
+    }
+
     fn partial_content(path: &Path, ranges: RangeList) -> ioResult<Self> {
         use std::fs::File;
         use std::io::{Read, Seek, SeekFrom};
 
-        let mut ret_buff = Vec::new();
         if !path.exists() {
-            Ok(Self::not_found())
-        } else {
-            let len = path.metadata()?.len();
+            return Ok(Self::not_found());
+        }
+
+        let len = path.metadata()?.len();
+        let spans = ranges.resolve(len);
+
+        if spans.is_empty() {
+            return Ok(Self::range_not_satisfiable(len));
+        } else if spans.len() > CONFIG.max_ranges {
+            warn!(
+                "{} ranges requested for '{}', sending the whole file",
+                spans.len(),
+                path.display()
+            );
+            return Ok(Self::file_response(path));
+        }
+
+        let open_span = |span: &ByteSpan| -> ioResult<_> {
             let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(span.start))?;
 
-            for range in ranges.ranges.iter() {
-                if range.start > len as i64
-                    || range.end.unwrap_or(0) > len as i64
-                {
-                    return Ok(Self::range_not_satisfiable());
-                }
+            Ok(file.take(span.len()))
+        };
 
-                if range.end.is_none() {
-                    if range.start < 0 {
-                        file.seek(SeekFrom::End(range.start))?;
-                        file.read_to_end(&mut ret_buff)?;
-                    } else {
-                        file.seek(SeekFrom::Start(range.start as u64))?;
-                        file.read_to_end(&mut ret_buff)?;
-                    }
-                } else {
-                    //Plus 1 because it's right inclusive
-                    let mut temp_buff =
-                        vec![
-                            0;
-                            ((range.end.unwrap() - range.start) as usize) + 1
-                        ];
-
-                    file.seek(SeekFrom::Start(range.start as u64))?;
-                    file.read(&mut temp_buff)?;
-
-                    ret_buff.append(&mut temp_buff);
-                }
-            }
+        let desc = map_file(path);
+        let mut headers = HeaderList::response_headers();
 
-            let desc = map_file(path);
+        let data: Box<dyn std::io::Read> = if spans.len() == 1 {
+            let span = &spans[0];
 
-            let mut headers = HeaderList::response_headers();
             headers.content(
                 &desc.typ.to_string(),
-                desc.charset,
-                ret_buff.len(),
+                desc.charset.clone(),
+                span.len() as usize,
+            );
+            headers.content_range(Some(*span), len);
+
+            Box::new(open_span(span)?)
+        } else {
+            let boundary = format!(
+                "{:x}",
+                md5::compute(format!("{} {}", path.display(), Utc::now()))
+            );
+
+            let part_type = match desc.charset {
+                Some(ref charset) => format!("{}; charset={}", desc.typ, charset),
+                None => desc.typ.to_string(),
+            };
+
+            //Each part is streamed straight out of the file, only the
+            //part headers are kept in memory.
+            let mut body: Box<dyn std::io::Read> = Box::new(std::io::empty());
+            let mut body_len = 0;
+            for span in spans.iter() {
+                let part_head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\n\
+                     Content-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary,
+                    part_type,
+                    span.start,
+                    span.end,
+                    len
+                );
+
+                body_len += part_head.len() + span.len() as usize;
+                body = Box::new(
+                    body.chain(Cursor::new(part_head.into_bytes()))
+                        .chain(open_span(span)?),
+                );
+            }
+
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            body_len += tail.len();
+            body = Box::new(body.chain(Cursor::new(tail.into_bytes())));
+
+            headers.content(
+                &format!("multipart/byteranges; boundary={}", boundary),
+                None,
+                body_len,
             );
 
-            headers.content_range(&ranges, Some(len as usize));
+            body
+        };
+
+        headers.content_language(&desc.lang);
+        headers.accept_ranges();
 
-            headers.content_language(&desc.lang);
+        let modified = path.metadata().and_then(|meta| meta.modified());
+        if let Ok(modified) = modified {
+            headers.last_modified(&modified.into());
+        }
 
-            Ok(Self {
-                data:    Some(ret_buff.into()),
-                headers,
-                code:    StatusCode::PartialContent,
-            })
+        if let Ok(etag) = file_etag(path) {
+            headers.etag(&etag);
+        }
+
+        Ok(Self {
+            data:    Some(ResponseData::Stream(data)),
+            headers,
+            code:    StatusCode::PartialContent,
+        })
+    }
+
+    /// Checks the If-Range header against the current state of the
+    /// file. Only a strong etag or an exact modification date match,
+    /// otherwise the whole file should be sent instead of the ranges.
+    fn if_range_matches(path: &Path, req: &Request) -> bool {
+        use chrono::DateTime;
+
+        let cond = match req.headers.get(IF_RANGE) {
+            Some(cond) => cond.trim(),
+            None => return true,
+        };
+
+        if cond.starts_with("W/") {
+            false
+        } else if cond.starts_with("\"") {
+            match file_etag(path) {
+                Ok(etag) => etag == cond,
+                Err(err) => {
+                    warn!("failed to generate etag for If-Range: '{}'", err);
+                    false
+                }
+            }
+        } else {
+            let date = match HeaderList::parse_date(cond) {
+                Some(date) => date,
+                None => return false,
+            };
+
+            match path.metadata().and_then(|meta| meta.modified()) {
+                Ok(modified) => {
+                    let modified: DateTime<Utc> = modified.into();
+                    modified.timestamp() == date.timestamp()
+                }
+                Err(_) => false,
+            }
         }
     }
 
@@ -451,7 +675,7 @@ impl Response {
             } else {
                 return Response::redirect(&path, StatusCode::MovedPermanently);
             }
-        } else if req.headers.has(RANGE) {
+        } else if req.headers.has(RANGE) && Self::if_range_matches(path, req) {
             use std::io::ErrorKind::*;
 
             let range_str = req.headers.get(RANGE).unwrap();
@@ -475,15 +699,28 @@ impl Response {
                 },
                 Err(err) => {
                     warn!(
-                        "issue parsing ranges '{}', err was: '{}'",
+                        "issue parsing ranges '{}', ignoring them: '{}'",
                         range_str, err
                     );
-                    Self::internal_error()
+                    Self::file_response(path)
                 }
             }
         } else {
             if path.exists() {
-                Self::file_response(path)
+                let siblings = precompressed_siblings(path, &req.headers);
+
+                let mut resp = match siblings.chosen {
+                    Some((sibling, coding)) => {
+                        Self::precompressed_response(path, &sibling, coding)
+                    }
+                    None => Self::file_response(path),
+                };
+
+                if siblings.exist {
+                    resp.headers.vary(ACCEPT_ENCODING);
+                }
+
+                resp
             } else {
                 use content_negotiator::NegotiationError::*;
 
@@ -491,17 +728,27 @@ impl Response {
                     ContentNegotiator::new(path, &req.headers).best_choice();
 
                 match list {
-                    Ok(mut list) => Self::file_response(&list.pop().unwrap()),
+                    Ok(mut list) => {
+                        metrics::negotiated(Negotiation::Chosen);
+                        Self::file_response(&list.pop().unwrap())
+                    },
                     Err(err) => match err {
-                        NotAcceptable => Self::not_acceptable(),
+                        NotAcceptable => {
+                            metrics::negotiated(Negotiation::NotAcceptable);
+                            Self::not_acceptable()
+                        },
                         MultipleResponses(list) => {
+                            metrics::negotiated(Negotiation::MultipleChoices);
                             let mut headers = HeaderList::response_headers();
                             let alt = format_alternates(list);
                             headers.alternates(alt);
 
                             Self::multiple_choices(headers)
                         }
-                        _ => Self::not_found(),
+                        _ => {
+                            metrics::negotiated(Negotiation::NoMatch);
+                            Self::not_found()
+                        },
                     },
                 }
             }
@@ -509,9 +756,14 @@ impl Response {
     }
 
     pub fn directory_listing(path: &Path) -> Self {
-        match DirectoryListing::new(path) {
+        //Only offer the upload form where the files could be saved.
+        let uploads = AuthHandler::new(path)
+            .map(|handler| handler.allows().contains(&Method::Put))
+            .unwrap_or(false);
+
+        match DirectoryListing::new(path, uploads) {
             Ok(dir) => {
-                let data = TERA.render("directory.html", &dir);
+                let data = TERA.render("directory.html", &tera::Context::from_serialize(&dir).unwrap());
                 let mut headers = HeaderList::response_headers();
 
                 match data {
@@ -550,7 +802,10 @@ impl Response {
         let handler = CgiHandler::new(remote, path, req);
         match handler {
             Ok(handler) => {
-                match handler.run() {
+                let res = handler.run();
+                metrics::cgi_run(res.is_err());
+
+                match res {
                     Ok(resp) => resp,
                     Err(err) => {
                         error!(
@@ -562,6 +817,7 @@ impl Response {
                 }
             },
             Err(err) => {
+                metrics::cgi_run(true);
                 error!(
                     "error occurred while processing CGI response: '{}'",
                     err
@@ -605,6 +861,94 @@ impl Response {
         )
     }
 
+    /// Compresses the body of the response with the best coding the
+    /// client accepts, if the content type and size make it worth
+    /// doing.
+    pub fn compress(&mut self, req: &Request) {
+        use std::io::Read;
+
+        if !CONFIG.compression.enabled
+            || self.data.is_none()
+            || self.headers.has(CONTENT_ENCODING)
+            || self.code == StatusCode::PartialContent
+        {
+            return;
+        }
+
+        let compressible = self
+            .headers
+            .get(CONTENT_TYPE)
+            .map(is_compressible)
+            .unwrap_or(false);
+
+        if !compressible {
+            return;
+        }
+
+        let size = match self.data {
+            Some(ResponseData::Buffer(ref buff)) => Some(buff.len()),
+            _ => self
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|len| len.parse().ok()),
+        };
+
+        if size.unwrap_or(usize::max_value()) < CONFIG.compression.min_size {
+            return;
+        }
+
+        self.headers.vary(ACCEPT_ENCODING);
+
+        let coding = match negotiate_coding(&req.headers) {
+            Some(coding) => coding,
+            None => return,
+        };
+
+        if let Some(etag) = self.headers.get(ETAG).map(String::from) {
+            self.headers.etag(&encoded_etag(&etag, coding.name()));
+        }
+
+        self.headers.content_encoding(coding.name());
+
+        //Buffers are small enough to be compressed up front so the
+        //length is known, streams are encoded as they're written out.
+        match self.data.take().unwrap() {
+            ResponseData::Buffer(buff) => {
+                let mut compressed = Vec::new();
+                let mut encoder = coding.encoder(Box::new(Cursor::new(buff)));
+
+                if let Err(err) = encoder.read_to_end(&mut compressed) {
+                    error!("failed to compress response body: '{}'", err);
+                    *self = Response::internal_error();
+                    return;
+                }
+
+                trace!(
+                    "compressed response with '{}' to {} bytes",
+                    coding.name(),
+                    compressed.len()
+                );
+
+                self.headers.remove(TRANSFER_ENCODING);
+                self.headers.content_length(compressed.len());
+                self.data = Some(compressed.into());
+            }
+            ResponseData::Stream(stream) => {
+                self.headers.chunked_encoding();
+                self.data = Some(ResponseData::Stream(coding.encoder(stream)));
+            }
+        }
+    }
+
+    /// Turns the response to a GET into the one to a HEAD of the same
+    /// resource, which carries every header the body would have been
+    /// sent with, down to its coding, but not the body itself.
+    pub fn head(mut self, req: &Request) -> Self {
+        self.compress(req);
+        self.data = None;
+        self
+    }
+
     fn write_w_timeout<'a, T>(writer: &'a mut T, dat: &[u8]) -> ioResult<()>
     where
         T: std::io::Write + Sized,
@@ -639,7 +983,38 @@ impl Response {
         Ok(())
     }
 
-    pub fn write_self<'a, T>(self, writer: &'a mut T) -> ioResult<()>
+    fn write_stream<'a, T>(
+        writer: &'a mut T,
+        stream: &mut dyn std::io::Read,
+    ) -> ioResult<()>
+    where
+        T: std::io::Write + Sized,
+    {
+        use std::io::ErrorKind;
+
+        let mut write_buff = vec![0; CONFIG.chunk_size.max(2048)];
+        loop {
+            match stream.read(&mut write_buff) {
+                Ok(siz) => {
+                    if siz == 0 {
+                        break;
+                    } else {
+                        Self::write_w_timeout(writer, &write_buff[0..siz])?;
+                    }
+                }
+                Err(err) => match err.kind() {
+                    ErrorKind::WouldBlock => continue,
+                    _ => return Err(err),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the response, returning how many bytes of body were
+    /// written after the head.
+    pub fn write_self<'a, T>(self, writer: &'a mut T) -> ioResult<u64>
     where
         T: std::io::Write + Sized,
     {
@@ -651,48 +1026,26 @@ impl Response {
 
         Self::write_w_timeout(writer, &mut write_buff)?;
 
+        let mut body = CountingWriter::new(writer);
         match self.data {
             Some(dat) => {
                 use ResponseData::*;
                 match dat {
-                    Buffer(buff) => Self::write_w_timeout(writer, &buff)?,
-                    Stream(mut stream) => {
-                        use std::io::ErrorKind;
-
-                        write_buff = vec![0; 2048];
-                        loop {
-                            match stream.read(&mut write_buff) {
-                                Ok(siz) => {
-                                    if siz == 0 {
-                                        break;
-                                    } else {
-                                        Self::write_w_timeout(
-                                            writer,
-                                            &write_buff[0..siz],
-                                        )?;
-                                    }
-                                }
-                                Err(err) => match err.kind() {
-                                    ErrorKind::WouldBlock => continue,
-                                    _ => return Err(err),
-                                },
-                            }
-                        }
-                    }
+                    Buffer(buff) => Self::write_w_timeout(&mut body, &buff)?,
+                    Stream(mut stream) => Self::write_stream(&mut body, &mut stream)?,
                 };
             }
             None => (),
         }
-        Ok(())
+        Ok(body.count)
     }
 
-    pub fn write_chunked<'a, T>(self, writer: &'a mut T) -> ioResult<()>
+    /// Writes the response with a chunked body, returning how many
+    /// bytes of encoded body were written after the head.
+    pub fn write_chunked<'a, T>(self, writer: &'a mut T) -> ioResult<u64>
     where
         T: std::io::Write + Sized,
     {
-        use std::io::BufReader;
-        use std::io::BufRead;
-
         let num = self.code.to_num();
 
         let mut write_buff = Vec::new();
@@ -707,54 +1060,44 @@ impl Response {
                     ResponseData::Buffer(buff)   =>
                         Box::new(Cursor::new(buff)),
                     ResponseData::Stream(stream) =>
-                        Box::new(stream),
+                        stream,
                 };
 
-                let reader   = BufReader::new(reader);
-                let mut buff = String::new();
-                for (ind, line) in reader.lines().enumerate() {
-                    let line = line?;
-                    if ind % 2 == 0 {
-                        buff.push_str(&format!("{}\r\n", line));
-                    }else{
-                        buff.push_str(&format!("{}\r\n", line));
-                        Self::write_w_timeout(
-                            writer,
-                            &format!("{:x}\r\n", buff.len()).into_bytes(),
-                        )?;
-                        Self::write_w_timeout(writer, buff.as_bytes())?;
-                        Self::write_w_timeout(
-                            writer,
-                            &format!("\r\n").into_bytes(),
-                        )?;
-
-                        buff.clear();
-                    }
-                }
-
-                if !buff.is_empty() {
-                    Self::write_w_timeout(
-                        writer,
-                        &format!("{:x}\r\n", buff.len()).into_bytes(),
-                    )?;
-                    Self::write_w_timeout(writer, &buff.into_bytes())?;
-                    Self::write_w_timeout(
-                        writer,
-                        &format!("\r\n").into_bytes(),
-                    )?;
-                }
+                let mut encoder = ChunkedEncoder::new(
+                    reader,
+                    CONFIG.chunk_size,
+                    &self.headers.trailers(),
+                );
 
-                Self::write_w_timeout(
-                    writer,
-                    &format!("0\r\n\r\n").into_bytes(),
-                )?;
-                Ok(())
+                let mut body = CountingWriter::new(writer);
+                Self::write_stream(&mut body, &mut encoder)?;
+                Ok(body.count)
             }
-            None => Ok(()),
+            None => Ok(0),
         }
     }
 }
 
+/// Passes writes through, counting the bytes that made it.
+struct CountingWriter<'a, T: Write> {
+    inner: &'a mut T,
+    count: u64,
+}
+
+impl<'a, T: Write> CountingWriter<'a, T> {
+    fn new(inner: &'a mut T) -> Self { Self { inner, count: 0 } }
+}
+
+impl<'a, T: Write> Write for CountingWriter<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> ioResult<usize> {
+        let siz = self.inner.write(buf)?;
+        self.count += siz as u64;
+        Ok(siz)
+    }
+
+    fn flush(&mut self) -> ioResult<()> { self.inner.flush() }
+}
+
 struct FileDescriptor {
     pub typ:     Mime,
     pub lang:    String,
@@ -762,6 +1105,9 @@ struct FileDescriptor {
     pub charset: Option<String>,
 }
 
+/// The media type a file is served with.
+pub fn content_type(file: &Path) -> String { map_file(file).typ.to_string() }
+
 fn map_file(file: &Path) -> FileDescriptor {
     use mime::*;
 
@@ -810,6 +1156,7 @@ fn map_encoding(path: &Path, desc: &mut FileDescriptor) {
 
         let enc = match ext.as_str() {
             "gz" => Some(headers::encoding::GZIP.into()),
+            "br" => Some(headers::encoding::BROTLI.into()),
             "zip" | "Z" => Some(headers::encoding::COMPRESS.into()),
             _ => {
                 if let Some(stem) = path.file_stem() {
@@ -925,3 +1272,36 @@ fn format_alternates(paths: Vec<(u32, PathBuf)>) -> String {
 
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_matches_get() {
+        let req: Request = "HEAD /a.txt HTTP/1.1\r\nHost: localhost\r\n\
+                            Accept-Encoding: gzip\r\n\r\n"
+            .parse()
+            .unwrap();
+
+        let text = "hello ".repeat(100).into_bytes();
+        let mut headers = HeaderList::response_headers();
+        headers.content(&mime::TEXT_PLAIN.to_string(), None, text.len());
+        headers.etag("\"abc\"");
+
+        let resp = || Response {
+            code:    StatusCode::Ok,
+            headers: headers.clone(),
+            data:    Some(text.clone().into()),
+        };
+
+        let mut get = resp();
+        get.compress(&req);
+        let head = resp().head(&req);
+
+        assert_eq!(head.headers, get.headers);
+        assert_eq!(head.headers.get(CONTENT_ENCODING), Some(encoding::GZIP));
+        assert_eq!(head.headers.get(VARY), Some("accept-encoding"));
+        assert!(head.data.is_none());
+    }
+}
@@ -286,6 +286,9 @@ impl<'a> CgiHandler<'a> {
             ("REQUEST_METHOD".into(),
              req.method.to_string()
             ),
+            ("REQUEST_ID".into(),
+             request_id::current().unwrap_or_default()
+            ),
             ("SERVER_PROTOCOL".into(),
              "HTTP/1.1".into()
             ),
@@ -0,0 +1,137 @@
+use crate::webserver::shared::headers::*;
+
+use std::io::{Cursor, Read, Result as ioResult, Write};
+
+///A reader adapter that produces the chunked transfer coding of
+///everything read out of the inner reader. Chunks are filled up to
+///`chunk_size` bytes before being emitted, and the data is never
+///interpreted, so binary bodies come out untouched.
+///
+///Trailers named in the response's Trailer header are computed while
+///the body streams through, and written after the last chunk.
+pub struct ChunkedEncoder<R: Read> {
+    inner:      R,
+    chunk_size: usize,
+    digest:     Option<md5::Context>,
+    pending:    Cursor<Vec<u8>>,
+    done:       bool,
+}
+
+impl<R: Read> ChunkedEncoder<R> {
+    pub fn new(inner: R, chunk_size: usize, trailers: &[String]) -> Self {
+        let wants = |name: &str| {
+            trailers.iter().any(|trailer| trailer.eq_ignore_ascii_case(name))
+        };
+
+        Self {
+            inner,
+            chunk_size: chunk_size.max(1),
+            digest: if wants(DIGEST) {
+                Some(md5::Context::new())
+            } else {
+                None
+            },
+            pending: Cursor::new(Vec::new()),
+            done: false,
+        }
+    }
+
+    ///Reads the next chunk out of the inner reader and encodes it
+    ///into the pending buffer, along with the terminating chunk once
+    ///the inner reader runs dry.
+    fn fill(&mut self) -> ioResult<()> {
+        use std::io::ErrorKind;
+
+        let mut chunk = vec![0; self.chunk_size];
+        let mut len = 0;
+        while len < chunk.len() {
+            match self.inner.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(siz) => len += siz,
+                Err(err) => match err.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                },
+            }
+        }
+
+        let mut out = Vec::with_capacity(len + 16);
+        if len > 0 {
+            if let Some(ref mut digest) = self.digest {
+                digest.consume(&chunk[0..len]);
+            }
+
+            write!(out, "{:x}\r\n", len)?;
+            out.extend_from_slice(&chunk[0..len]);
+            out.extend_from_slice(b"\r\n");
+        }
+
+        if len < chunk.len() {
+            out.extend_from_slice(b"0\r\n");
+
+            if let Some(digest) = self.digest.take() {
+                write!(
+                    out,
+                    "Digest: md5={}\r\n",
+                    base64::encode(&digest.compute().0)
+                )?;
+            }
+
+            out.extend_from_slice(b"\r\n");
+            self.done = true;
+        }
+
+        self.pending = Cursor::new(out);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkedEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> ioResult<usize> {
+        loop {
+            let siz = self.pending.read(buf)?;
+            if siz > 0 || self.done || buf.is_empty() {
+                return Ok(siz);
+            }
+
+            self.fill()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(data: &[u8], chunk_size: usize, trailers: &[String]) -> Vec<u8> {
+        let mut out = Vec::new();
+        ChunkedEncoder::new(data, chunk_size, trailers)
+            .read_to_end(&mut out)
+            .unwrap();
+
+        out
+    }
+
+    #[test]
+    fn test_binary_chunks() {
+        let data = b"ab\ncd\x00\xff\r\n";
+
+        assert_eq!(
+            encode(data, 4, &[]),
+            b"4\r\nab\nc\r\n4\r\nd\x00\xff\r\r\n1\r\n\n\r\n0\r\n\r\n".to_vec()
+        );
+        assert_eq!(encode(b"", 4, &[]), b"0\r\n\r\n".to_vec());
+        assert_eq!(encode(b"abcd", 4, &[]), b"4\r\nabcd\r\n0\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_digest_trailer() {
+        let out = encode(b"hello", 1024, &[String::from("Digest")]);
+
+        let expected = format!(
+            "5\r\nhello\r\n0\r\nDigest: md5={}\r\n\r\n",
+            base64::encode(&md5::compute(b"hello").0)
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
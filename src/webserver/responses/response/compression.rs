@@ -0,0 +1,272 @@
+use crate::webserver::shared::headers::*;
+use crate::CONFIG;
+
+use flate2::read::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use mime::Mime;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+///The content codings the server is able to apply to a response
+///on the fly, in order of preference when the client rates them
+///equally.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Coding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    pub fn supported() -> &'static [Coding] {
+        &[
+            #[cfg(feature = "brotli")]
+            Coding::Brotli,
+            Coding::Gzip,
+            Coding::Deflate,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli  => encoding::BROTLI,
+            Coding::Gzip    => encoding::GZIP,
+            Coding::Deflate => encoding::DEFLATE,
+        }
+    }
+
+    ///Wraps the given reader so that everything read out of it
+    ///is encoded with this coding.
+    pub fn encoder(&self, inner: Box<dyn Read>) -> Box<dyn Read> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => {
+                Box::new(brotli::CompressorReader::new(inner, 4096, 5, 22))
+            }
+            Coding::Gzip => Box::new(GzEncoder::new(inner, Compression::default())),
+            Coding::Deflate => {
+                Box::new(DeflateEncoder::new(inner, Compression::default()))
+            }
+        }
+    }
+}
+
+///Finds the rating the client gave to a content coding in its
+///Accept-Encoding header, falling back on the wildcard. Unlisted
+///codings are rated zero.
+fn coding_rating(entries: &RankedEntryList<String>, name: &str) -> u32 {
+    let find = |name: &str| -> Option<u32> {
+        entries
+            .iter()
+            .find(|entry| entry.entry.eq_ignore_ascii_case(name))
+            .map(|entry| entry.rating.unwrap_or(1000))
+    };
+
+    find(name).or(find("*")).unwrap_or(0)
+}
+
+fn accepted_codings(headers: &HeaderList) -> Option<RankedEntryList<String>> {
+    let accept = headers.get(ACCEPT_ENCODING)?;
+
+    RankedEntryList::new_list(accept).ok()
+}
+
+///Picks the best coding out of the ones supported based on the
+///Accept-Encoding header. A missing header means only identity is
+///acceptable, as does a header that rates all of ours at zero.
+pub fn negotiate_coding(headers: &HeaderList) -> Option<Coding> {
+    let entries = accepted_codings(headers)?;
+
+    let mut best: Option<(u32, Coding)> = None;
+    for coding in Coding::supported().iter() {
+        let rating = coding_rating(&entries, coding.name());
+
+        if rating == 0 {
+            continue;
+        }
+
+        match best {
+            Some((best_rating, _)) if best_rating >= rating => (),
+            _ => best = Some((rating, *coding)),
+        }
+    }
+
+    best.map(|(_, coding)| coding)
+}
+
+///The extensions of precompressed siblings we look for, in order of
+///preference, along with the coding they were compressed with.
+static SIBLINGS: &[(&str, &str)] =
+    &[("br", encoding::BROTLI), ("gz", encoding::GZIP)];
+
+///The outcome of looking for precompressed copies of a file.
+pub struct Siblings {
+    ///Whether any usable sibling exists, in which case the response
+    ///varies on Accept-Encoding no matter which one is picked.
+    pub exist:  bool,
+    ///The best sibling the client accepts, along with its coding.
+    pub chosen: Option<(PathBuf, &'static str)>,
+}
+
+///Looks for `<file>.br` and `<file>.gz` next to the requested file.
+///Siblings older than the original are considered stale and ignored.
+pub fn precompressed_siblings(path: &Path, headers: &HeaderList) -> Siblings {
+    let mut ret = Siblings {
+        exist:  false,
+        chosen: None,
+    };
+
+    if !CONFIG.compression.precompressed {
+        return ret;
+    }
+
+    let modified = match path.metadata().and_then(|meta| meta.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return ret,
+    };
+
+    let entries = accepted_codings(headers);
+
+    let mut best = 0;
+    for (ext, coding) in SIBLINGS.iter() {
+        let sibling = PathBuf::from(format!("{}.{}", path.display(), ext));
+
+        let fresh = sibling
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .map(|sib_modified| sib_modified >= modified)
+            .unwrap_or(false);
+
+        if !fresh || !sibling.is_file() {
+            continue;
+        }
+
+        ret.exist = true;
+
+        let rating = entries
+            .as_ref()
+            .map(|entries| coding_rating(entries, coding))
+            .unwrap_or(0);
+
+        if rating > best {
+            best = rating;
+            ret.chosen = Some((sibling, coding));
+        }
+    }
+
+    ret
+}
+
+///Checks the given content type against the configured list of
+///types that are worth compressing.
+pub fn is_compressible(typ: &str) -> bool {
+    let typ: Mime = match typ.parse() {
+        Ok(typ) => typ,
+        Err(_) => return false,
+    };
+
+    CONFIG
+        .compression
+        .mime_types
+        .iter()
+        .filter_map(|allowed| allowed.parse::<Mime>().ok())
+        .any(|allowed| {
+            (allowed.type_() == "*" || allowed.type_() == typ.type_())
+                && (allowed.subtype() == "*"
+                    || allowed.subtype() == typ.subtype())
+        })
+}
+
+///Produces the etag of an encoded variant of a representation, so
+///caches don't mix up the compressed and uncompressed bodies.
+pub fn encoded_etag(etag: &str, coding: &str) -> String {
+    let trimmed = etag.trim_end_matches('"');
+    format!("{}-{}\"", trimmed, coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(val: &str) -> HeaderList {
+        format!("Accept-Encoding: {}", val).parse().unwrap()
+    }
+
+    #[test]
+    fn test_negotiate_coding() {
+        assert_eq!(negotiate_coding(&HeaderList::default()), None);
+        assert_eq!(
+            negotiate_coding(&accepting("gzip")),
+            Some(Coding::Gzip)
+        );
+        assert_eq!(
+            negotiate_coding(&accepting("gzip;q=0.5, deflate")),
+            Some(Coding::Deflate)
+        );
+        assert_eq!(
+            negotiate_coding(&accepting("identity, gzip;q=0")),
+            None
+        );
+        assert_eq!(
+            negotiate_coding(&accepting("*;q=0.2, deflate;q=0.1")),
+            Some(Coding::supported()[0])
+        );
+    }
+
+    #[test]
+    fn test_encoded_etag() {
+        assert_eq!(encoded_etag("\"abc\"", encoding::GZIP), "\"abc-gzip\"");
+    }
+
+    #[test]
+    fn test_precompressed_siblings() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = std::env::temp_dir()
+            .join(format!("compression-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("b.txt.gz")).unwrap();
+
+        let now = SystemTime::now();
+        let write = |name: &str, modified: SystemTime| {
+            let path = dir.join(name);
+            std::fs::write(&path, name).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        write("a.txt", now);
+        write("a.txt.gz", now + Duration::from_secs(10));
+        write("a.txt.br", now - Duration::from_secs(10));
+        //Older than the directory in the way of its sibling.
+        write("b.txt", now - Duration::from_secs(60));
+        write("c.txt", now);
+
+        //The brotli sibling is older than the file, so only gzip counts.
+        let siblings =
+            precompressed_siblings(&dir.join("a.txt"), &accepting("br, gzip"));
+        assert!(siblings.exist);
+        assert_eq!(
+            siblings.chosen,
+            Some((dir.join("a.txt.gz"), encoding::GZIP))
+        );
+
+        let siblings =
+            precompressed_siblings(&dir.join("a.txt"), &accepting("br"));
+        assert!(siblings.exist);
+        assert_eq!(siblings.chosen, None);
+
+        for name in &["b.txt", "c.txt", "missing.txt"] {
+            let siblings =
+                precompressed_siblings(&dir.join(name), &accepting("gzip"));
+            assert!(!siblings.exist, "{}", name);
+            assert_eq!(siblings.chosen, None, "{}", name);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use super::map_file;
 use crate::webserver::shared::headers::*;
+use chrono::{DateTime, Utc};
 use mime::*;
 use std::path::{Path, PathBuf};
 
@@ -29,6 +30,29 @@ impl<'a, 'b> ContentNegotiator<'a, 'b> {
         }
     }
 
+    /// Picks the candidate closest to, but not after, the datetime given
+    /// in Accept-Datetime, as a Memento TimeGate does. Asking for a time
+    /// before the first candidate gets the first one.
+    pub fn best_datetime<T>(
+        &self,
+        mut candidates: Vec<(DateTime<Utc>, T)>,
+    ) -> Option<(DateTime<Utc>, T)> {
+        let wanted = HeaderList::parse_date(self.headers.get(ACCEPT_DATETIME)?)?;
+
+        candidates.sort_by_key(|(time, _)| *time);
+
+        let pos = candidates
+            .iter()
+            .rposition(|(time, _)| *time <= wanted)
+            .unwrap_or(0);
+
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates.swap_remove(pos))
+        }
+    }
+
     pub fn best_choice(&self) -> Result<Vec<PathBuf>, NegotiationError> {
         let mut paths = Vec::new();
         let stub: String =
@@ -35,10 +35,11 @@ pub struct FileInfo {
 pub struct DirectoryListing {
     dir_path: PathBuf,
     files:    Vec<FileInfo>,
+    uploads:  bool,
 }
 
 impl DirectoryListing {
-    pub fn new(path: &Path) -> std::io::Result<Self> {
+    pub fn new(path: &Path, uploads: bool) -> std::io::Result<Self> {
         use std::time::SystemTime;
 
         let mut files = Vec::new();
@@ -61,6 +62,7 @@ impl DirectoryListing {
         Ok(Self {
             dir_path: path.file_name().unwrap_or(Default::default()).into(),
             files,
+            uploads,
         })
     }
 }
@@ -4,6 +4,7 @@ pub enum StatusCode {
     Created,
     NoContent,
     PartialContent,
+    MultiStatus,
     MultipleChoice,
     MovedPermanently,
     Found,
@@ -15,11 +16,18 @@ pub enum StatusCode {
     NotFound,
     NotAcceptable,
     RequestTimeout,
+    Conflict,
     PreconditionFailed,
+    PayloadTooLarge,
     RangeNotSatisfiable,
+    UnsupportedMediaType,
+    Locked,
+    FailedDependency,
     InternalServerError,
     NotImplemented,
+    BadGateway,
     VersionNotSupported,
+    InsufficientStorage,
     Custom(String, usize)
 }
 
@@ -31,6 +39,7 @@ impl StatusCode {
             Created             => 201,
             NoContent           => 204,
             PartialContent      => 206,
+            MultiStatus         => 207,
             MultipleChoice      => 300,
             MovedPermanently    => 301,
             Found               => 302,
@@ -42,11 +51,18 @@ impl StatusCode {
             NotAllowed          => 405,
             NotAcceptable       => 406,
             RequestTimeout      => 408,
+            Conflict            => 409,
             PreconditionFailed  => 412,
+            PayloadTooLarge     => 413,
             RangeNotSatisfiable => 416,
+            UnsupportedMediaType => 415,
+            Locked              => 423,
+            FailedDependency    => 424,
             InternalServerError => 500,
             NotImplemented      => 501,
+            BadGateway          => 502,
             VersionNotSupported => 505,
+            InsufficientStorage => 507,
             Custom(_, n)        => *n
         }
     }
@@ -59,6 +75,7 @@ impl StatusCode {
             201 => Created,
             204 => NoContent,
             206 => PartialContent,
+            207 => MultiStatus,
             300 => MultipleChoice,
             301 => MovedPermanently,
             302 => Found,
@@ -70,11 +87,18 @@ impl StatusCode {
             405 => NotAllowed,
             406 => NotAcceptable,
             408 => RequestTimeout,
+            409 => Conflict,
             412 => PreconditionFailed,
+            413 => PayloadTooLarge,
             416 => RangeNotSatisfiable,
+            415 => UnsupportedMediaType,
+            423 => Locked,
+            424 => FailedDependency,
             500 => InternalServerError,
             501 => NotImplemented,
+            502 => BadGateway,
             505 => VersionNotSupported,
+            507 => InsufficientStorage,
             _   => Custom(String::new(), num)
         }
     }
@@ -92,18 +116,26 @@ impl Display for StatusCode {
             Forbidden           => "Forbidden",
             InternalServerError => "Internal Server Error",
             VersionNotSupported => "HTTP Version Not Supported",
+            InsufficientStorage => "Insufficient Storage",
             BadRequest          => "Bad Request",
             NotImplemented      => "Not Implemented",
+            BadGateway          => "Bad Gateway",
             NotAllowed          => "Not Allowed",
             MovedPermanently    => "Moved Permanently",
             Found               => "Found",
             NotModified         => "Not Modified",
             RequestTimeout      => "Request Timeout",
+            Conflict            => "Conflict",
             PreconditionFailed  => "Precondition Failed",
+            PayloadTooLarge     => "Payload Too Large",
             PartialContent      => "Partial Content",
+            MultiStatus         => "Multi-Status",
             MultipleChoice      => "Multiple Choice",
             NotAcceptable       => "Not Acceptable",
             RangeNotSatisfiable => "Range Not Satisfiable",
+            UnsupportedMediaType => "Unsupported Media Type",
+            Locked              => "Locked",
+            FailedDependency    => "Failed Dependency",
             Unauthorized        => "Authorization Required",
             Custom(msg, _)      => msg,
         };
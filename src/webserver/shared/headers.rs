@@ -58,17 +58,33 @@ define_const! {
     ACCEPT_CHARSET      = "accept-charset",
     ACCEPT_ENCODING     = "accept-encoding",
     ACCEPT_LANGUAGE     = "accept-language",
-    ACCEPT_RANGE        = "accept-range",
+    ACCEPT_RANGES       = "accept-ranges",
     NEGOTIATE           = "negotiate",
     RANGE               = "range",
     USER_AGENT          = "user-agent",
     REFERER             = "referer",
     TRANSFER_ENCODING   = "transfer-encoding",
+    TRAILER             = "trailer",
+    TE                  = "te",
+    DIGEST              = "digest",
+    ACCEPT_DATETIME     = "accept-datetime",
+    MEMENTO_DATETIME    = "memento-datetime",
+    LINK                = "link",
+    DAV                 = "dav",
+    DEPTH               = "depth",
+    DESTINATION         = "destination",
+    OVERWRITE           = "overwrite",
+    IF                  = "if",
+    LOCK_TOKEN          = "lock-token",
+    TIMEOUT             = "timeout",
+    ACCEPT_PATCH        = "accept-patch",
     ALTERNATES          = "alternates",
     TCN                 = "tcn",
     AUTHORIZATION       = "authorization",
+    COOKIE              = "cookie",
     AUTHENTICATION_INFO = "authentication-info",
-    WWW_AUTHENTICATE    = "www-authenticate"
+    WWW_AUTHENTICATE    = "www-authenticate",
+    X_REQUEST_ID        = "x-request-id"
 }
 
 /// The list of constants corresponding to the acceptable values of
@@ -85,13 +101,16 @@ pub mod connection {
 pub mod encoding {
     define_const! {
         GZIP     = "gzip",
-        COMPRESS = "compress"
+        COMPRESS = "compress",
+        DEFLATE  = "deflate",
+        BROTLI   = "br",
+        IDENTITY = "identity"
     }
 }
 
 /// A wrapper around a hashmap that provides
 /// convienience functions for dealing with headers.
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, PartialEq, Default, Clone)]
 pub struct HeaderList(HashMap<String, String>);
 
 use std::error::Error;
@@ -255,6 +274,11 @@ impl HeaderList {
         Self(ret)
     }
 
+    /// Parses a date in the format used by HTTP headers.
+    pub fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+        Utc.datetime_from_str(date.trim(), "%a, %d %b %Y %T GMT").ok()
+    }
+
     /// Used to retrieve a date stored under the given header name.
     pub fn get_date(&self, name: &str) -> Option<DateTime<Utc>> {
         let date = self.0.get(name)?;
@@ -311,6 +335,25 @@ impl HeaderList {
         self.0.insert(ALLOW.into(), buff);
     }
 
+    /// Advertises support for WebDAV, class 2 meaning locking.
+    pub fn dav(&mut self) {
+        self.0.insert(DAV.into(), "1, 2".into());
+        self.0.insert("ms-author-via".into(), "DAV".into());
+    }
+
+    pub fn accept_patch(&mut self, types: &[&str]) {
+        self.0.insert(ACCEPT_PATCH.into(), types.join(", "));
+    }
+
+    pub fn lock_token(&mut self, token: &str) {
+        self.0.insert(LOCK_TOKEN.into(), format!("<{}>", token));
+    }
+
+    /// Advertises support for byte range requests
+    pub fn accept_ranges(&mut self) {
+        self.0.insert(ACCEPT_RANGES.into(), "bytes".into());
+    }
+
     pub fn chunked_encoding(&mut self) {
         self.0.insert(TRANSFER_ENCODING.into(), "chunked".into());
 
@@ -318,13 +361,47 @@ impl HeaderList {
     }
 
     pub fn is_chunked(&self) -> bool {
-        if let Some(enc) = self.0.get(TRANSFER_ENCODING.into()) {
+        if let Some(enc) = self.0.get(TRANSFER_ENCODING) {
             enc == "chunked"
         } else {
             false
         }
     }
 
+    /// Declares a field that will be sent as a trailer after the last
+    /// chunk of the body.
+    pub fn trailer(&mut self, field: &str) {
+        match self.0.get_mut(TRAILER) {
+            Some(trailer) => trailer.push_str(&format!(", {}", field)),
+            None => {
+                self.0.insert(TRAILER.into(), field.into());
+            }
+        }
+    }
+
+    /// The list of fields declared in the trailer header.
+    pub fn trailers(&self) -> Vec<String> {
+        match self.0.get(TRAILER) {
+            Some(trailer) => trailer
+                .split(",")
+                .map(|field| field.trim().to_lowercase())
+                .filter(|field| !field.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the client indicated it is willing to accept trailer
+    /// fields in a chunked response.
+    pub fn accepts_trailers(&self) -> bool {
+        match self.0.get(TE) {
+            Some(te) => te
+                .split(",")
+                .any(|coding| coding.trim().eq_ignore_ascii_case("trailers")),
+            None => false,
+        }
+    }
+
     pub fn authorization(&self) -> Option<&String> { self.0.get(AUTHORIZATION) }
 
     /// Sets the location header
@@ -332,29 +409,12 @@ impl HeaderList {
         self.0.insert(LOCATION.into(), path);
     }
 
-    pub fn content_range(&mut self, ranges: &RangeList, total: Option<usize>) {
-        let st = if let Some((min, max)) = ranges.get_bounds() {
-            format!(
-                "{} {}-{}/{}",
-                ranges.unit,
-                min,
-                max,
-                if let Some(total) = total {
-                    total.to_string()
-                } else {
-                    "*".into()
-                }
-            )
-        } else {
-            format!(
-                "{} */{}",
-                ranges.unit,
-                if let Some(total) = total {
-                    total.to_string()
-                } else {
-                    "*".into()
-                }
-            )
+    /// Sets the content range header, a missing span produces the
+    /// unsatisfied form used by 416 responses.
+    pub fn content_range(&mut self, span: Option<ByteSpan>, total: u64) {
+        let st = match span {
+            Some(span) => format!("bytes {}-{}/{}", span.start, span.end, total),
+            None => format!("bytes */{}", total),
         };
 
         self.0.insert(CONTENT_RANGE.into(), st);
@@ -362,13 +422,47 @@ impl HeaderList {
 
     pub fn content_encoding(&mut self, enc: &str) {
         match enc {
-            encoding::COMPRESS | encoding::GZIP => {
+            encoding::COMPRESS
+            | encoding::GZIP
+            | encoding::DEFLATE
+            | encoding::BROTLI => {
                 self.0.insert(CONTENT_ENCODING.into(), enc.into());
             }
             _ => log::warn!("invalid encoding type: '{}'", enc),
         };
     }
 
+    /// Adds a field to the vary header, keeping any that were
+    /// already present.
+    pub fn vary(&mut self, field: &str) {
+        match self.0.get_mut(VARY) {
+            Some(vary) => {
+                let exists = vary
+                    .split(",")
+                    .any(|f| f.trim().eq_ignore_ascii_case(field));
+
+                if !exists {
+                    vary.push_str(&format!(", {}", field));
+                }
+            }
+            None => {
+                self.0.insert(VARY.into(), field.into());
+            }
+        }
+    }
+
+    pub fn content_location(&mut self, loc: String) {
+        self.0.insert(CONTENT_LOCATION.into(), loc);
+    }
+
+    pub fn memento_datetime(&mut self, time: &DateTime<Utc>) {
+        self.0.insert(MEMENTO_DATETIME.into(), Self::format_date(time));
+    }
+
+    pub fn link(&mut self, links: String) {
+        self.0.insert(LINK.into(), links);
+    }
+
     pub fn alternates(&mut self, s: String) {
         self.0.insert(ALTERNATES.into(), s.into());
     }
@@ -386,6 +480,10 @@ impl HeaderList {
         self.0.insert(WWW_AUTHENTICATE.into(), val);
     }
 
+    pub fn request_id(&mut self, id: &str) {
+        self.0.insert(X_REQUEST_ID.into(), id.into());
+    }
+
     pub fn content_length(&mut self, len: usize) {
         self.0.insert(CONTENT_LENGTH.into(), len.to_string());
     }
@@ -403,13 +501,19 @@ impl HeaderList {
         }
     }
 
+    /// Sets a header without interpreting it, for headers that are
+    /// passed along as they were received.
+    pub fn set(&mut self, name: &str, val: &str) {
+        self.0.insert(name.to_lowercase(), val.into());
+    }
+
     pub fn remove(&mut self, val: &str) {
         self.0.remove(val);
     }
 
     pub fn has(&self, what: &str) -> bool { self.0.get(what).is_some() }
 
-    fn format_date(date: &DateTime<Utc>) -> String {
+    pub fn format_date(date: &DateTime<Utc>) -> String {
         date.format("%a, %d %b %Y %T GMT").to_string()
     }
 }
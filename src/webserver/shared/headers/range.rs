@@ -57,25 +57,130 @@ impl FromStr for Range {
     }
 }
 
+/// A range resolved against the length of a representation, both
+/// ends are inclusive.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ByteSpan {
+    pub start: u64,
+    pub end:   u64,
+}
+
+impl ByteSpan {
+    pub fn len(&self) -> u64 { self.end - self.start + 1 }
+}
+
+impl Range {
+    /// Resolves the range against a representation of the given
+    /// length. Suffix and open ended ranges are clamped to the end of
+    /// the representation, and `None` is returned if no part of the
+    /// range can be satisfied.
+    pub fn resolve(&self, len: u64) -> Option<ByteSpan> {
+        if len == 0 {
+            return None;
+        }
+
+        if self.start < 0 {
+            let suffix = self.start.abs() as u64;
+
+            Some(ByteSpan {
+                start: len.saturating_sub(suffix),
+                end:   len - 1,
+            })
+        } else {
+            let start = self.start as u64;
+            if start >= len {
+                return None;
+            }
+
+            let end = match self.end {
+                Some(end) if end < self.start => return None,
+                Some(end) => (end as u64).min(len - 1),
+                None => len - 1,
+            };
+
+            Some(ByteSpan { start, end })
+        }
+    }
+}
+
 pub struct RangeList {
     pub ranges: Vec<Range>,
     pub unit:   String,
 }
 
 impl RangeList {
-    pub fn get_bounds(&self) -> Option<(usize, usize)> {
-        if self.ranges.len() > 1 {
-            None
-        } else {
-            if self.ranges[0].end.is_none() {
-                None
-            } else {
-                Some((
-                    self.ranges[0].start as usize,
-                    self.ranges[0].end.unwrap() as usize,
-                ))
+    /// Resolves every range against the given length, dropping the
+    /// ones that can't be satisfied. Overlapping and adjacent ranges
+    /// are coalesced, so the spans come back sorted and disjoint.
+    pub fn resolve(&self, len: u64) -> Vec<ByteSpan> {
+        let mut spans: Vec<ByteSpan> = self
+            .ranges
+            .iter()
+            .filter_map(|range| range.resolve(len))
+            .collect();
+
+        spans.sort_by_key(|span| span.start);
+
+        let mut ret: Vec<ByteSpan> = Vec::new();
+        for span in spans.into_iter() {
+            match ret.last_mut() {
+                Some(last) if span.start <= last.end + 1 => {
+                    last.end = last.end.max(span.end);
+                }
+                _ => ret.push(span),
             }
         }
+
+        ret
+    }
+}
+
+/// The value of a Content-Range header sent along with a request
+/// payload, saying where in the target the payload belongs.
+#[derive(Debug, PartialEq)]
+pub struct ContentRange {
+    pub span:  ByteSpan,
+    pub total: Option<u64>,
+}
+
+impl FromStr for ContentRange {
+    type Err = RangeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use RangeError::*;
+
+        let s = s.trim();
+        let rest = if s.starts_with("bytes ") {
+            &s[6..]
+        } else {
+            return Err(UnknownUnit(s.into()));
+        };
+
+        let pieces: Vec<_> = rest.splitn(2, "/").map(|s| s.trim()).collect();
+        if pieces.len() != 2 {
+            return Err(InvalidRange(s.into()));
+        }
+
+        let bounds: Vec<_> = pieces[0].splitn(2, "-").collect();
+        if bounds.len() != 2 {
+            return Err(InvalidRange(s.into()));
+        }
+
+        let span = ByteSpan {
+            start: bounds[0].parse().map_err(InvalidNumber)?,
+            end:   bounds[1].parse().map_err(InvalidNumber)?,
+        };
+
+        let total = match pieces[1] {
+            "*" => None,
+            total => Some(total.parse().map_err(InvalidNumber)?),
+        };
+
+        if span.end < span.start || total.map_or(false, |total| span.end >= total)
+        {
+            return Err(InvalidRange(s.into()));
+        }
+
+        Ok(Self { span, total })
     }
 }
 
@@ -187,4 +292,47 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_resolve() {
+        let spans = |s: &str, len: u64| {
+            s.parse::<RangeList>().unwrap().resolve(len)
+        };
+        let span = |start, end| ByteSpan { start, end };
+
+        assert_eq!(spans("bytes=0-9", 100), &[span(0, 9)]);
+        assert_eq!(spans("bytes=90-200", 100), &[span(90, 99)]);
+        assert_eq!(spans("bytes=50-", 100), &[span(50, 99)]);
+        assert_eq!(spans("bytes=-10", 100), &[span(90, 99)]);
+        assert_eq!(spans("bytes=-500", 100), &[span(0, 99)]);
+        assert_eq!(spans("bytes=100-", 100), &[]);
+        assert_eq!(spans("bytes=0-0", 0), &[]);
+        assert_eq!(
+            spans("bytes=50-60, 0-9, 55-70, 71-80, 90-", 100),
+            &[span(0, 9), span(50, 80), span(90, 99)]
+        );
+        assert_eq!(spans("bytes=0-10, -95", 100), &[span(0, 99)]);
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            "bytes 10-19/100".parse(),
+            Ok(ContentRange {
+                span:  ByteSpan { start: 10, end: 19 },
+                total: Some(100),
+            })
+        );
+        assert_eq!(
+            "bytes 0-4/*".parse(),
+            Ok(ContentRange {
+                span:  ByteSpan { start: 0, end: 4 },
+                total: None,
+            })
+        );
+        assert!("bytes 5-4/*".parse::<ContentRange>().is_err());
+        assert!("bytes 0-100/100".parse::<ContentRange>().is_err());
+        assert!("bytes */100".parse::<ContentRange>().is_err());
+        assert!("items 0-4/5".parse::<ContentRange>().is_err());
+    }
 }
@@ -69,6 +69,10 @@ impl<T: FromStr> RankedEntryList<T> {
         ret
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &RankedEntry<T>> {
+        self.0.iter()
+    }
+
     pub fn has_zeroes(&self) -> bool {
         for item in self.0.iter() {
             if let Some(score) = item.rating {
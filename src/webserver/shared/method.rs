@@ -7,6 +7,14 @@ pub enum Method {
     Put,
     Post,
     Delete,
+    Propfind,
+    Proppatch,
+    Mkcol,
+    Copy,
+    Move,
+    Lock,
+    Unlock,
+    Patch,
 }
 
 impl Default for Method {
@@ -40,6 +48,14 @@ impl FromStr for Method {
             "PUT"     => Ok(Method::Put),
             "POST"    => Ok(Method::Post),
             "DELETE"  => Ok(Method::Delete),
+            "PROPFIND"  => Ok(Method::Propfind),
+            "PROPPATCH" => Ok(Method::Proppatch),
+            "MKCOL"     => Ok(Method::Mkcol),
+            "COPY"      => Ok(Method::Copy),
+            "MOVE"      => Ok(Method::Move),
+            "LOCK"      => Ok(Method::Lock),
+            "UNLOCK"    => Ok(Method::Unlock),
+            "PATCH"     => Ok(Method::Patch),
             _ => Err(UnknownMethodError(String::from(s))),
         }
     }
@@ -60,6 +76,14 @@ impl Display for Method {
                 Method::Delete  => "DELETE",
                 Method::Options => "OPTIONS",
                 Method::Trace   => "TRACE",
+                Method::Propfind  => "PROPFIND",
+                Method::Proppatch => "PROPPATCH",
+                Method::Mkcol     => "MKCOL",
+                Method::Copy      => "COPY",
+                Method::Move      => "MOVE",
+                Method::Lock      => "LOCK",
+                Method::Unlock    => "UNLOCK",
+                Method::Patch     => "PATCH",
             }
         )
     }
@@ -76,4 +100,14 @@ mod tests {
 
         assert!(as_enum.is_ok());
     }
+
+    #[test]
+    fn parse_webdav_methods() {
+        for name in &["PROPFIND", "PROPPATCH", "MKCOL", "COPY", "MOVE", "LOCK", "UNLOCK", "PATCH"] {
+            let method: Method = name.parse().unwrap();
+            assert_eq!(method.to_string(), *name);
+        }
+
+        assert!("propfind".parse::<Method>().is_err());
+    }
 }
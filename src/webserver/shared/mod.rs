@@ -3,3 +3,8 @@ pub use headers::*;
 
 pub mod method;
 pub use method::*;
+
+pub mod uuid;
+pub use uuid::*;
+
+pub mod request_id;
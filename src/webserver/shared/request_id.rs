@@ -0,0 +1,59 @@
+use super::headers::{HeaderList, X_REQUEST_ID};
+use super::uuid::uuid;
+use std::cell::RefCell;
+
+/// The longest id taken from a client, anything longer gets replaced.
+const MAX_LEN: usize = 128;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Picks the id of a request, keeping the one the client sent when it's
+/// safe to echo and log, or generating a fresh one.
+pub fn resolve(headers: &HeaderList) -> String {
+    headers
+        .get(X_REQUEST_ID)
+        .map(str::trim)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_LEN
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map_or_else(uuid, String::from)
+}
+
+/// Sets the id of the request handled by this thread, or clears it once
+/// the request is done.
+pub fn set(id: Option<String>) {
+    CURRENT.with(|current| *current.borrow_mut() = id);
+}
+
+/// The id of the request handled by this thread, if any.
+pub fn current() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut headers = HeaderList::default();
+        assert_eq!(resolve(&headers).len(), 36);
+
+        headers.request_id("abc-123");
+        assert_eq!(resolve(&headers), "abc-123");
+
+        for bad in &["", "has space", "new\nline", &"x".repeat(200)] {
+            headers.request_id(bad);
+            assert_ne!(&resolve(&headers), bad);
+        }
+
+        set(Some("abc-123".into()));
+        assert_eq!(current().as_deref(), Some("abc-123"));
+        set(None);
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,31 @@
+use chrono::Utc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Produces a fresh uuid, derived from the time, the process and a
+/// counter, which is unique enough for ids handed out by one server.
+pub fn uuid() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let seed = format!(
+        "{}-{}-{}",
+        Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+
+    let mut bytes = md5::compute(seed).0;
+    //Mark it as a version 4, variant 1 uuid.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String =
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
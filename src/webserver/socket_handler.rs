@@ -1,6 +1,16 @@
 pub mod auth_handler;
 pub use auth_handler::*;
 pub mod etag;
+pub mod hooks;
+pub mod memento;
+pub mod multipart;
+pub mod patch;
+pub mod preconditions;
+pub mod quota;
+pub mod removal;
+pub mod upload;
+pub mod versions;
+pub mod webdav;
 
 use is_executable::IsExecutable;
 
@@ -11,26 +21,23 @@ use super::shared::*;
 
 use std::io::Read;
 use std::net::{SocketAddr, TcpStream};
-use std::time::SystemTime;
 
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::path::{Path, PathBuf};
-use std::sync::RwLock;
+use std::path::PathBuf;
 
 use log::*;
 
+use super::access_log;
+use super::audit;
 use super::clf::*;
+use super::metrics;
+use super::status::{self, State};
+use super::warc::{self, Exchange};
 use crate::CONFIG;
 
 type Result<T> = std::result::Result<T, SocketError>;
 
-lazy_static::lazy_static! {
-    static ref LOG_LIST: RwLock<Vec<LogEntry>> = {
-        Default::default()
-    };
-}
-
 pub struct SocketHandler {
     stream:   TcpStream,
     addr:     SocketAddr,
@@ -43,6 +50,7 @@ pub enum SocketError {
     RequestError(RequestParsingError),
     NoTerminator,
     ConnectionClosed,
+    PayloadTooLarge(i64),
 }
 
 impl Display for SocketError {
@@ -54,6 +62,7 @@ impl Display for SocketError {
             RequestError(err) => write!(f, "{}", err),
             NoTerminator      => write!(f, "no terminator found at the end of the request"),
             ConnectionClosed => write!(f, "connection closed by user"),
+            PayloadTooLarge(len) => write!(f, "body of {} bytes is over the limit", len),
         }
     }
 }
@@ -84,14 +93,43 @@ impl SocketHandler {
         loop {
             log::trace!("waiting for request...");
             let req = self.read_request();
+            let started = std::time::Instant::now();
+            let id = match &req {
+                Ok(req) => request_id::resolve(&req.headers),
+                Err(_) => uuid(),
+            };
+            request_id::set(Some(id.clone()));
+            if let Ok(req) = &req {
+                let line = format!(
+                    "{} {} {}",
+                    req.method,
+                    req.path.display(),
+                    req.ver
+                );
+                status::set(&self.addr, State::Writing, Some(line));
+            }
 
             let mut passed_auth = None;
+            let mut auth_user = None;
+            let audit = match &req {
+                Ok(req) => audit::Pending::begin(
+                    &self.addr,
+                    req,
+                    &SocketHandler::sterilize_path(&req.path)
+                ),
+                Err(_) => None,
+            };
+
             //If the response failed to be parsed, send a bad request
             let mut resp: Response = match &req {
                 Ok(req) => {
                     debug!("\n---->\n{:#?}", req);
                     if req.ver != "HTTP/1.1" {
                         Response::unsupported_version()
+                    } else if versions::is_store_path(
+                        &SocketHandler::sterilize_path(&req.path)
+                    ) {
+                        Response::forbidden()
                     } else {
                         let url = SocketHandler::sterilize_path(&req.path);
 
@@ -104,6 +142,7 @@ impl SocketHandler {
 
                                     if res != Passed {
                                         passed_auth = Some(false);
+                                        metrics::auth_failed();
                                         warn!(
                                             "connection '{}' failed \
                                              authentication",
@@ -119,24 +158,46 @@ impl SocketHandler {
                                         }
                                     } else {
                                         passed_auth = Some(true);
+                                        if auth_handler.requires_auth() {
+                                            auth_user =
+                                                AuthHandler::supplied_user(req);
+                                        }
+                                        if let Some(locked) =
+                                            webdav::check_locks(req, &url)
+                                        {
+                                            locked
+                                        }else{
                                         match req.method {
                                             Method::Get => self.get(&req),
                                             Method::Head => {
-                                                let mut resp = self.get(&req);
-                                                resp.data = None;
-                                                resp
+                                                self.get(&req).head(&req)
                                             },
                                             Method::Options => {
                                                 self.options(&req)
                                             },
                                             Method::Trace   =>
                                                 self.trace(&req),
-                                            Method::Put     =>
-                                                self.put(&req),
-                                            Method::Delete  =>
-                                                self.delete(&req),
-                                            Method::Post    =>
-                                                self.post(&req)
+                                            Method::Put     => self.put(
+                                                &req,
+                                                auth_user.as_deref()
+                                            ),
+                                            Method::Delete  => self.delete(
+                                                &req,
+                                                auth_user.as_deref()
+                                            ),
+                                            Method::Post    => self.post(
+                                                &req,
+                                                auth_user.as_deref()
+                                            ),
+                                            Method::Patch   => self.patch(
+                                                &req,
+                                                auth_user.as_deref()
+                                            ),
+                                            _ => self.webdav(
+                                                &req,
+                                                auth_user.as_deref()
+                                            ),
+                                        }
                                         }
                                     }
                                 }
@@ -176,6 +237,10 @@ impl SocketHandler {
                         ConnectionClosed => {
                             return Ok(());
                         }
+                        PayloadTooLarge(_) => {
+                            warn!("refusing request: '{}'", err);
+                            Response::payload_too_large()
+                        }
                         _ => {
                             error!("error parsing request:\n\t{}", err);
                             Response::bad_request()
@@ -185,11 +250,24 @@ impl SocketHandler {
             };
 
             let conn: String;
+            let mut entry = None;
             match &req {
                 Ok(req) => {
-                    let entry = LogEntry::new(&self.addr, &req, &resp);
-                    let mut list = LOG_LIST.write().unwrap();
-                    list.push(entry);
+                    resp.compress(req);
+
+                    if resp.headers.is_chunked()
+                        && req.headers.accepts_trailers()
+                    {
+                        resp.headers.trailer(headers::DIGEST);
+                    }
+
+                    if let Some(audit) = audit {
+                        audit.finish(&resp, auth_user.clone());
+                    }
+
+                    entry = Some(
+                        LogEntry::new(&self.addr, req, &resp, auth_user)
+                    );
 
                     if let Some(passed) = passed_auth {
                         if passed {
@@ -221,8 +299,33 @@ impl SocketHandler {
                 .connection(&conn);
 
             resp.headers.connection(&conn);
-            self.write_response(resp)?;
+            resp.headers.request_id(&id);
+
+            let exchange = match &req {
+                Ok(req) => Exchange::begin(req, &mut resp),
+                Err(_) => None,
+            };
+
+            let status = resp.code.to_num();
+            let written = self.write_response(resp);
+            let sent = written.as_ref().map_or(0, |siz| *siz);
+            if let Ok(req) = &req {
+                metrics::request(&req.method, status, started.elapsed(), sent);
+            }
+            if let Some(mut entry) = entry {
+                entry.finish(sent, started.elapsed());
+                access_log::record(entry);
+            }
+
+            written?;
             trace!("response written to '{}'", self.addr);
+            status::served();
+            status::set(&self.addr, State::Idle, None);
+
+            if let Some(exchange) = exchange {
+                exchange.finish();
+            }
+            request_id::set(None);
 
             match conn.to_lowercase().as_str() {
                 connection::CLOSE => break,
@@ -262,6 +365,10 @@ impl SocketHandler {
             match self.stream.read(&mut in_buff) {
                 Ok(siz) => {
                     if siz != 0 {
+                        if self.req_buff.is_empty() {
+                            status::set(&self.addr, State::Reading, None);
+                        }
+
                         let dat = &in_buff[0..siz];
                         self.req_buff.extend_from_slice(dat);
                         start = Instant::now();
@@ -303,45 +410,77 @@ impl SocketHandler {
             self.addr
         );
 
+        let head_len = req_str.len();
         let mut req: Request = req_str.parse()?;
         if let Some(len) = req.headers.get(headers::CONTENT_LENGTH) {
             let len: i64 = len.trim()
                 .parse()
                 .unwrap_or(0);
 
+            //Refused before reading any of it, the connection gets closed
+            //rather than reading past the body.
+            if len > CONFIG.max_body_size as i64 {
+                return Err(SocketError::PayloadTooLarge(len));
+            }
+
             let diff = len - self.req_buff.len() as i64;
             if diff <= 0 {
-                let mut payload = self.req_buff.split_off((len - 1) as usize);
+                let mut payload = self.req_buff.split_off(len as usize);
                 std::mem::swap(&mut payload, &mut self.req_buff);
                 req.set_payload(payload);
             }else{
-                let mut buff = vec![0; diff as usize];
-                self.stream.read_exact(&mut buff)?;
-
                 let mut temp: Vec<_> = self.req_buff.split_off(0);
 
-                temp.append(&mut buff);
+                //The stream doesn't block, so the rest of the body is read
+                //the same way the headers are, as it arrives.
+                while (temp.len() as i64) < len {
+                    if Instant::now() - start >= CONFIG.read_timeout {
+                        use std::io::{Error, ErrorKind};
+                        return Err(Error::from(ErrorKind::TimedOut).into());
+                    }
+
+                    let want = (len - temp.len() as i64) as usize;
+                    let want = want.min(in_buff.len());
+                    match self.stream.read(&mut in_buff[0..want]) {
+                        Ok(0) => return Err(SocketError::ConnectionClosed),
+                        Ok(siz) => {
+                            temp.extend_from_slice(&in_buff[0..siz]);
+                            start = Instant::now();
+                        }
+                        Err(err) => {
+                            use std::io::ErrorKind;
+                            match err.kind() {
+                                ErrorKind::WouldBlock => continue,
+                                _ => return Err(err.into()),
+                            }
+                        }
+                    }
+                }
+
                 req.set_payload(temp);
             }
         }
 
+        metrics::received(
+            (head_len + req.payload.as_ref().map_or(0, |load| load.len())) as u64
+        );
+
         debug!("remaining buffer length: {}", self.req_buff.len());
         Ok(req)
     }
 
-    fn write_response(&mut self, resp: Response) -> Result<()> {
+    ///Writes the response, returning how many bytes of body were sent.
+    fn write_response(&mut self, resp: Response) -> Result<u64> {
         debug!("\n<----\n{:#?}", resp);
-        match resp.headers.is_chunked() {
-            true => {
-                resp.write_chunked(&mut self.stream)?;
-            }
+        let sent = match resp.headers.is_chunked() {
+            true => resp.write_chunked(&mut self.stream)?,
             false => resp.write_self(&mut self.stream)?,
         };
 
-        Ok(())
+        Ok(sent)
     }
 
-    fn sterilize_path(path: &PathBuf) -> PathBuf {
+    pub(crate) fn sterilize_path(path: &PathBuf) -> PathBuf {
         let has_slash = path.as_os_str().to_string_lossy().ends_with("/");
 
         let rel_path = if path.starts_with("/") {
@@ -358,29 +497,61 @@ impl SocketHandler {
     }
 
     fn get(&mut self, req: &Request) -> Response {
+        use preconditions::*;
+
         let url = SocketHandler::sterilize_path(&req.path);
 
         if url.starts_with(&CONFIG.root) {
-            let not_mod = SocketHandler::check_if_match(req, &url)
-                .or(SocketHandler::check_modified_since(req, &url))
-                .or(SocketHandler::check_unmodified_since(req, &url))
-                .or(SocketHandler::check_if_none_match(req, &url));
-
-            if let Some(not_mod) = not_mod {
-                not_mod
-            }else {
-                let comp =
-                    CONFIG.root.join(PathBuf::from(".well-known/access.log"));
-                if url.clone() == comp {
-                    SocketHandler::log_response()
-                }else {
-                    if     url.is_executable()
-                       && !url.is_dir()
-                    {
-                        Response::cgi_response(self.addr.clone(), &url, req)
-                    }else{
-                        Response::path_response(&url, req)
-                    }
+            let viewer = CONFIG.root.join(&CONFIG.log_viewer.path);
+            let metrics = CONFIG.root.join(&CONFIG.metrics.path);
+            if CONFIG.log_viewer.enabled && url == viewer {
+                access_log::viewer_response(req, &url)
+            }else if CONFIG.server_status.enabled
+                && url == CONFIG.root.join(&CONFIG.server_status.path)
+            {
+                status::status_response(req, &url, &self.addr)
+            }else if CONFIG.metrics.enabled
+                && CONFIG.metrics.port.is_none()
+                && url == metrics
+            {
+                metrics::metrics_response()
+            }else if url == CONFIG.root.join(&CONFIG.audit.path) {
+                audit::audit_response(req, &url)
+            }else if warc::replaying() {
+                warc::replay_response(req)
+            }else if let Some(resp) = SocketHandler::versions_response(req, &url) {
+                resp
+            }else if url.is_executable() && !url.is_dir() {
+                Response::cgi_response(self.addr.clone(), &url, req)
+            }else if !url.exists() {
+                Response::path_response(&url, req)
+            }else{
+                let state = ResourceState::of(&url);
+
+                match evaluate(&req.method, &req.headers, &state) {
+                    Precondition::Proceed => {
+                        let mut resp = Response::path_response(&url, req);
+                        if CONFIG.versioning.enabled && url.is_file() {
+                            memento::memento_headers(
+                                &mut resp.headers,
+                                &req.path.display().to_string(),
+                                None,
+                            );
+                            resp.headers.vary(ACCEPT_DATETIME);
+                        }
+                        resp
+                    },
+                    Precondition::NotModified => {
+                        let mut resp = Response::not_modified(&url);
+                        if let Some(ref etag) = state.etag {
+                            resp.headers.etag(etag);
+                        }
+                        if let Some(ref modified) = state.modified {
+                            resp.headers.last_modified(modified);
+                        }
+                        resp
+                    },
+                    Precondition::Failed => Response::precondition_failed(),
                 }
             }
         } else {
@@ -388,7 +559,7 @@ impl SocketHandler {
         }
     }
 
-    fn post(&mut self, req: &Request) -> Response {
+    fn post(&mut self, req: &Request, user: Option<&str>) -> Response {
         let url = SocketHandler::sterilize_path(&req.path);
 
         if url.starts_with(&CONFIG.root) {
@@ -396,6 +567,8 @@ impl SocketHandler {
                && !url.is_dir()
             {
                 Response::cgi_response(self.addr.clone(), &url, req)
+            }else if url.is_dir() {
+                SocketHandler::upload(req, &url, user)
             }else{
                 Response::bad_request()
             }
@@ -404,127 +577,143 @@ impl SocketHandler {
         }
     }
 
-    fn file_modified(path: &Path) -> Option<SystemTime> {
-        if let Ok(meta) = path.metadata() {
-            if let Ok(modi) = meta.modified() {
-                Some(modi)
-            } else {
-                warn!(
-                    "modified date for file '{}' couldn't be retrieved",
-                    path.display()
-                );
-                None
-            }
-        } else {
-            warn!(
-                "metadata for file '{}' couldn't be retrieved",
-                path.display()
+    /// Saves the files of a multipart/form-data POST into the directory.
+    fn upload(req: &Request, url: &PathBuf, user: Option<&str>) -> Response {
+        use multipart::*;
+
+        let boundary = match req.headers.get(CONTENT_TYPE).and_then(boundary) {
+            Some(boundary) => boundary,
+            None => return Response::bad_request(),
+        };
+
+        //Uploading creates files just like a PUT does.
+        let allowed = AuthHandler::new(url)
+            .map(|handler| handler.allows().contains(&Method::Put))
+            .unwrap_or(false);
+        if !allowed {
+            return Response::error(
+                StatusCode::Forbidden,
+                "Uploads are not allowed in this directory.",
+                HeaderList::response_headers()
             );
-            None
         }
-    }
 
-    fn check_modified_since(
-        req: &Request,
-        full_path: &Path,
-    ) -> Option<Response> {
-        if let Some(date) = req.headers.get_date(&headers::IF_MODIFIED_SINCE) {
-            let check_time: SystemTime = date.into();
-            let modi: SystemTime = Self::file_modified(full_path)?;
-
-            use chrono::{DateTime, Utc};
-            //If the file has been modified after the check_time
-            //then that means we want to just retrieve it.
-            //If it hasn't, not changed.
-            let temp: DateTime<Utc> = modi.into();
-            debug!("check: {} modified: {}", date, temp);
-            if check_time < modi {
-                debug!("has been modified");
-                None
-            } else {
-                debug!("hasn't been modified");
-                Some(Response::not_modified(full_path))
-            }
-        } else {
-            None
+        if let Some(failed) = SocketHandler::check_preconditions(req, url) {
+            return failed;
         }
-    }
 
-    fn check_unmodified_since(
-        req: &Request,
-        full_path: &Path,
-    ) -> Option<Response> {
-        if let Some(date) = req.headers.get_date(&headers::IF_UNMODIFIED_SINCE)
-        {
-            let check_time: SystemTime = date.into();
-            let modi: SystemTime = Self::file_modified(full_path)?;
+        let payload = req.payload.as_deref().unwrap_or_default();
+        match save_uploads(payload, &boundary, url, req, user) {
+            Ok(summary) => {
+                debug!(
+                    "saved {} of {} uploaded files to '{}'",
+                    summary.saved(),
+                    summary.files.len(),
+                    url.display()
+                );
 
-            if check_time >= modi {
-                None
-            } else {
-                Some(Response::not_modified(full_path))
+                let code = if summary.over_quota {
+                    StatusCode::InsufficientStorage
+                }else if summary.locked {
+                    StatusCode::Locked
+                }else if summary.saved() > 0 {
+                    StatusCode::Created
+                }else{
+                    StatusCode::Ok
+                };
+
+                let json = req
+                    .headers
+                    .get(ACCEPT)
+                    .map_or(false, |accept| accept.contains("application/json"));
+                if json {
+                    Response::json_response(code, &summary)
+                }else{
+                    Response::template_response(code, "upload.html", &summary)
+                }
+            },
+            Err(err) => {
+                warn!("failed to read upload to '{}': '{}'", url.display(), err);
+                match err {
+                    MultipartError::IoError(_) => Response::internal_error(),
+                    _ => Response::bad_request(),
+                }
             }
-        } else {
-            None
         }
     }
 
-    fn check_if_match(req: &Request, full_path: &Path) -> Option<Response> {
-        use etag::*;
-
-        match &req.headers.get(IF_MATCH) {
-            Some(etag) => {
-                let comp_etag = file_etag(full_path).ok()?;
-                if &comp_etag == *etag {
-                    None
-                } else {
-                    Some(Response::precondition_failed())
-                }
+    /// Evaluates the conditional headers of a state changing request,
+    /// producing the 412 to send back if any of them failed.
+    fn check_preconditions(req: &Request, url: &PathBuf) -> Option<Response> {
+        use preconditions::*;
+
+        match evaluate(&req.method, &req.headers, &ResourceState::of(url)) {
+            Precondition::Proceed => None,
+            _ => {
+                debug!(
+                    "precondition failed for {} on '{}'",
+                    req.method,
+                    url.display()
+                );
+                Some(Response::precondition_failed())
             }
-            None => None,
         }
     }
 
-    fn check_if_none_match(
-        req: &Request,
-        full_path: &Path,
-    ) -> Option<Response> {
-        use etag::*;
-
-        match &req.headers.get(IF_NONE_MATCH) {
-            Some(etags) => {
-                let etags: Vec<_> = etags.split(",").collect();
+    /// Sets the etag and last modified headers of a file that was just
+    /// written, so clients can make their next update conditional.
+    fn validators(url: &PathBuf, headers: &mut HeaderList) {
+        let state = preconditions::ResourceState::of(url);
 
-                let comp_etag = file_etag(full_path).ok()?;
-                for etag in etags.iter() {
-                    if comp_etag == *etag {
-                        return None;
-                    }
-                }
-                Some(Response::precondition_failed())
-            }
-            None => None,
+        if let Some(ref etag) = state.etag {
+            headers.etag(etag);
+        }
+        if let Some(ref modified) = state.modified {
+            headers.last_modified(modified);
         }
     }
 
-    fn log_response() -> Response {
-        let mut buff = String::new();
-        {
-            let log_list = LOG_LIST.read().unwrap();
-            for entry in log_list.iter() {
-                buff.push_str(&format!("{}\n", entry));
-            }
+    /// Answers the `?versions` and `?version=<id>` queries with the
+    /// list of kept versions of a file or the content of one of them,
+    /// along with the Memento TimeMap and TimeGate of the file.
+    fn versions_response(req: &Request, url: &PathBuf) -> Option<Response> {
+        if !CONFIG.versioning.enabled {
+            return None;
         }
 
-        let buff: Vec<u8> = buff.into();
-
-        let mut headers = HeaderList::response_headers();
-        headers.content(&mime::TEXT_PLAIN.to_string(), None, buff.len());
-
-        Response {
-            code: StatusCode::Ok,
-            headers,
-            data: Some(buff.into()),
+        if let Some(id) = req.query_param("version") {
+            match versions::find(url, &id) {
+                Some((version, content)) => {
+                    let mut resp = Response::file_response_as(url, &content);
+                    memento::memento_headers(
+                        &mut resp.headers,
+                        &req.path.display().to_string(),
+                        Some(&version.modified.unwrap_or(version.time)),
+                    );
+                    Some(resp)
+                }
+                None => Some(Response::not_found()),
+            }
+        } else if req.query_param("timemap").is_some() {
+            Some(memento::timemap_response(req, url))
+        } else if req.headers.has(ACCEPT_DATETIME) && !url.is_dir() {
+            memento::timegate_response(req, url)
+        } else if req.query_param("versions").is_some() {
+            match versions::list(url) {
+                Ok(list) => {
+                    Some(Response::json_response(StatusCode::Ok, &list))
+                }
+                Err(err) => {
+                    error!(
+                        "failed to list versions of '{}': '{}'",
+                        url.display(),
+                        err
+                    );
+                    Some(Response::internal_error())
+                }
+            }
+        } else {
+            None
         }
     }
 
@@ -538,59 +727,99 @@ impl SocketHandler {
         }
     }
 
-    fn put(&mut self, req: &Request) -> Response {
-        use std::fs::File;
-        use std::io::Write;
+    fn put(&mut self, req: &Request, user: Option<&str>) -> Response {
+        use upload::*;
 
         let url = SocketHandler::sterilize_path(&req.path);
         if url.starts_with(&CONFIG.root) {
+            if removal::is_protected(&url) {
+                return Response::forbidden();
+            }
+
+            if let Some(failed) = SocketHandler::check_preconditions(req, &url) {
+                return failed;
+            }
+
             let code = if url.exists() {
                 StatusCode::Ok
             }else{
                 StatusCode::Created
             };
 
-            match File::create(&url) {
-                Ok(mut file) => {
-                    match req.payload {
-                        Some(ref load) => {
-                            match file.write_all(load) {
-                                Ok(_) =>
-                                    Response {
-                                        code,
-                                        headers: HeaderList::response_headers(),
-                                        data: None
-                                    },
-                                Err(err) => {
-                                    use std::io::ErrorKind::*;
-                                    warn!(
-                                        "could not write file in PUT: '{}'", 
-                                        err
-                                    );
+            let load = match req.payload {
+                Some(ref load) => load,
+                None => {
+                    warn!("empty payload on PUT request");
+                    return Response::bad_request();
+                }
+            };
 
-                                    match err.kind() {
-                                        PermissionDenied =>
-                                            Response::forbidden(),
-                                        _ => 
-                                            Response::internal_error()
-                                    }
-                                }
-                            }
+            let range = match req.headers.get(CONTENT_RANGE) {
+                Some(range) => match range.parse::<ContentRange>() {
+                    Ok(range) => Some(range),
+                    Err(err) => {
+                        warn!("invalid content range on PUT: '{}'", err);
+                        return Response::bad_request();
+                    }
+                },
+                None => None,
+            };
 
-                        },
-                        None       => {
-                            warn!("empty payload on PUT request");
-                            Response::bad_request()
-                        }
+            let before = quota::size_of(&url);
+            let after = match range {
+                Some(ref range) => before
+                    .unwrap_or(0)
+                    .max(range.span.end + 1),
+                None => load.len() as u64,
+            };
+            let grow = after.saturating_sub(before.unwrap_or(0));
+
+            let reservation =
+                match quota::reserve(&url, user, grow, before.is_none()) {
+                    Ok(reservation) => reservation,
+                    Err(err) => {
+                        warn!("refusing PUT to '{}': '{}'", url.display(), err);
+                        return Response::insufficient_storage(&err);
+                    }
+                };
+
+            if let Err(err) = versions::keep(&url, req, user) {
+                error!(
+                    "failed to keep version of '{}', refusing PUT: '{}'",
+                    url.display(),
+                    err
+                );
+                return Response::internal_error();
+            }
+
+            match write_atomic(&url, load, range.as_ref()) {
+                Ok(_) => {
+                    reservation.record(&url, user, before);
+                    hooks::fire(
+                        req,
+                        &url,
+                        quota::size_of(&url).unwrap_or(0),
+                        user
+                    );
+
+                    let mut headers = HeaderList::response_headers();
+                    SocketHandler::validators(&url, &mut headers);
+                    headers.content_length(0);
+
+                    Response {
+                        code,
+                        headers,
+                        data: None
                     }
                 },
                 Err(err) => {
-                    error!(
-                        "failed to create file at '{}': '{}'",
+                    warn!(
+                        "could not write file '{}' in PUT: '{}'",
                         url.display(),
                         err
                     );
-                    Response::internal_error()
+
+                    SocketHandler::upload_failed(err)
                 }
             }
         }else{
@@ -598,37 +827,136 @@ impl SocketHandler {
         }
     }
 
-    fn delete(&mut self, req: &Request) -> Response {
+    fn upload_failed(err: upload::UploadError) -> Response {
+        use upload::UploadError;
+
+        match err {
+            UploadError::MissingParent(_) => Response::conflict(
+                "The directory the file would be placed in \
+                 does not exist."
+            ),
+            UploadError::IsDirectory(_) => Response::conflict(
+                "A directory already exists at that location."
+            ),
+            UploadError::RangeMismatch { .. } =>
+                Response::bad_request(),
+            UploadError::RangeNotSatisfiable(len) =>
+                Response::range_not_satisfiable(len),
+            UploadError::IoError(err) => {
+                use std::io::ErrorKind::*;
+
+                match err.kind() {
+                    PermissionDenied =>
+                        Response::forbidden(),
+                    _ =>
+                        Response::internal_error()
+                }
+            }
+        }
+    }
+
+    fn patch(&mut self, req: &Request, user: Option<&str>) -> Response {
+        use patch::*;
+
         let url = SocketHandler::sterilize_path(&req.path);
         if url.starts_with(&CONFIG.root) {
-            match std::fs::remove_file(&url) {
-                Ok(_) => {
-                    Response::error(
-                        StatusCode::Ok,
-                        &format!("File '{}' deleted successfully.", url.display()),
-                        HeaderList::response_headers()
-                    )
+            if removal::is_protected(&url) {
+                return Response::forbidden();
+            }
+
+            if let Some(failed) = SocketHandler::check_preconditions(req, &url) {
+                return failed;
+            }
+
+            let code = if url.exists() {
+                StatusCode::NoContent
+            }else{
+                StatusCode::Created
+            };
+
+            let load = match req.payload {
+                Some(ref load) => load,
+                None => {
+                    warn!("empty payload on PATCH request");
+                    return Response::bad_request();
+                }
+            };
+
+            //Patches never grow a file by more than they carry, give or
+            //take the formatting of merged JSON.
+            let before = quota::size_of(&url);
+            let reservation = match quota::reserve(
+                &url,
+                user,
+                load.len() as u64,
+                before.is_none()
+            ) {
+                Ok(reservation) => reservation,
+                Err(err) => {
+                    warn!("refusing PATCH to '{}': '{}'", url.display(), err);
+                    return Response::insufficient_storage(&err);
+                }
+            };
+
+            //Appends leave what was there untouched, and copying the whole
+            //file for each of them would grow the history quadratically.
+            let kept = if is_append(&req.headers) {
+                Ok(None)
+            }else{
+                versions::keep(&url, req, user)
+            };
+            if let Err(err) = kept {
+                error!(
+                    "failed to keep version of '{}', refusing PATCH: '{}'",
+                    url.display(),
+                    err
+                );
+                return Response::internal_error();
+            }
+
+            match apply(&url, &req.headers, load) {
+                Ok(()) => {
+                    reservation.record(&url, user, before);
+
+                    let mut headers = HeaderList::response_headers();
+                    SocketHandler::validators(&url, &mut headers);
+                    if code == StatusCode::Created {
+                        headers.content_length(0);
+                    }
+
+                    Response {
+                        code,
+                        headers,
+                        data: None
+                    }
                 },
                 Err(err) => {
-                    use std::io::ErrorKind::*;
-                    match err.kind() {
-                        PermissionDenied =>
-                            Response::forbidden(),
-                        NotFound => {
+                    warn!(
+                        "could not patch file '{}': '{}'",
+                        url.display(),
+                        err
+                    );
+
+                    match err {
+                        PatchError::UploadError(err) =>
+                            SocketHandler::upload_failed(err),
+                        PatchError::UnsupportedType(_) => {
+                            let mut headers = HeaderList::response_headers();
+                            headers.accept_patch(PATCH_TYPES);
                             Response::error(
-                                StatusCode::Ok,
-                                &format!("File '{}' deleted successfully.", url.display()),
-                                HeaderList::response_headers()
+                                StatusCode::UnsupportedMediaType,
+                                "The patch format is not supported for \
+                                 this file.",
+                                headers
                             )
                         },
-                        _ => {
-                            warn!(
-                                "error occurred during DELETE req at '{}': '{}'",
-                                url.display(),
-                                err
-                            );
-                            Response::internal_error()
-                        }
+                        PatchError::NotJson(_) => Response::conflict(
+                            "The file is not valid JSON, so it can't be \
+                             merged into."
+                        ),
+                        PatchError::InvalidPatch(_)
+                        | PatchError::InvalidRange(_) =>
+                            Response::bad_request(),
                     }
                 }
             }
@@ -637,6 +965,73 @@ impl SocketHandler {
         }
     }
 
+    fn delete(&mut self, req: &Request, user: Option<&str>) -> Response {
+        let url = SocketHandler::sterilize_path(&req.path);
+        if url.starts_with(&CONFIG.root) {
+            if let Some(failed) = SocketHandler::check_preconditions(req, &url) {
+                return failed;
+            }
+
+            let size = quota::size_of(&url).unwrap_or(0);
+            match removal::remove(&url, req, user) {
+                Ok(()) => {
+                    webdav::locks::forget(&url);
+                    webdav::props::forget(&url);
+                    hooks::fire(req, &url, size, user);
+                    Response::no_content()
+                },
+                Err(err) => SocketHandler::removal_failed(&url, err),
+            }
+        }else{
+            Response::forbidden()
+        }
+    }
+
+    fn removal_failed(url: &PathBuf, err: removal::RemovalError) -> Response {
+        use removal::RemovalError::*;
+
+        match err {
+            NotFound(_) => Response::not_found(),
+            Protected(_) | RecursionDisabled(_) | TooDeep(_) => {
+                warn!("refusing to delete '{}': '{}'", url.display(), err);
+                Response::error(
+                    StatusCode::Forbidden,
+                    &format!("{}.", err),
+                    HeaderList::response_headers()
+                )
+            },
+            IoError(ref io) if io.kind() == std::io::ErrorKind::PermissionDenied =>
+                Response::forbidden(),
+            IoError(_) => {
+                warn!(
+                    "error occurred during DELETE req at '{}': '{}'",
+                    url.display(),
+                    err
+                );
+                Response::internal_error()
+            }
+        }
+    }
+
+    fn webdav(&mut self, req: &Request, user: Option<&str>) -> Response {
+        let url = SocketHandler::sterilize_path(&req.path);
+        if url.starts_with(&CONFIG.root) {
+            match req.method {
+                Method::Propfind => webdav::propfind(req, &url),
+                Method::Proppatch => webdav::proppatch(req, &url),
+                Method::Mkcol => webdav::mkcol(req, &url),
+                Method::Copy | Method::Move => {
+                    webdav::copy_move(req, &url, user)
+                },
+                Method::Lock => webdav::lock(req, &url, user),
+                Method::Unlock => webdav::unlock(req, &url),
+                _ => Response::not_implemented(),
+            }
+        }else{
+            Response::forbidden()
+        }
+    }
+
     fn trace(&mut self, req: &Request) -> Response {
         Response::trace_response(req)
     }
@@ -38,6 +38,10 @@ impl AuthHandler {
         Ok(Self { auth_file })
     }
 
+    ///Whether an auth file applies, so requests passing the check have
+    ///had their credentials verified.
+    pub fn requires_auth(&self) -> bool { self.auth_file.is_some() }
+
     pub fn allows(&self) -> Vec<Method> {
         match self.auth_file {
             Some(ref file) => {
@@ -50,7 +54,8 @@ impl AuthHandler {
                 Method::Options,
                 Method::Trace,
                 Method::Get,
-                Method::Head
+                Method::Head,
+                Method::Propfind,
             ]
         }
     }
@@ -175,6 +180,19 @@ impl AuthHandler {
         }
     }
 
+    /// The name of the user the request was made on behalf of, taken
+    /// from its authorization header.
+    pub fn supplied_user(req: &Request) -> Option<String> {
+        let auth: SuppliedAuth = req.headers.authorization()?.parse().ok()?;
+        let (_, user) = auth.get_info();
+
+        if user.is_empty() {
+            None
+        } else {
+            Some(user)
+        }
+    }
+
     fn generate_nonce() -> String {
         format!(
             "{:x}",
@@ -262,6 +280,13 @@ impl AuthHandler {
         }
     }
 
+    ///Whether the path is an auth file, which must never be served,
+    ///replaced or removed through a request.
+    pub fn is_auth_file(path: &Path) -> bool {
+        path.file_name()
+            .map_or(false, |name| name.to_string_lossy() == CONFIG.auth.file_name)
+    }
+
     fn find_config(loc: &Path) -> Result<Option<AuthFile>, AuthFileParseError> {
         let file = loc.join(&CONFIG.auth.file_name);
         if file.exists() {
@@ -338,6 +363,7 @@ jbollen:66e0459d0abbc8cd8bd9a88cd226a9b2"#
                     Method::Trace,
                     Method::Head,
                     Method::Post,
+                    Method::Propfind,
                     Method::Put,
                     Method::Delete
                 ],
@@ -384,6 +410,7 @@ vaona:Colonial Place:fbcc0f347e4ade65a337a4febc421c81"#
                     Method::Trace,
                     Method::Head,
                     Method::Post,
+                    Method::Propfind,
                     Method::Put
                 ],
                 users:  vec![
@@ -407,6 +434,31 @@ vaona:Colonial Place:fbcc0f347e4ade65a337a4febc421c81"#
             },
             auth_file
         );
+
+        let auth_file: AuthFile = r#"authorization-type=Basic
+realm="Share"
+ALLOW-MKCOL
+ALLOW-COPY
+ALLOW-MOVE
+ALLOW-PROPPATCH
+ALLOW-LOCK
+ALLOW-PATCH
+a:0cc175b9c0f1b6a831c399e269772661"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            &auth_file.allows[6..],
+            &[
+                Method::Mkcol,
+                Method::Copy,
+                Method::Move,
+                Method::Proppatch,
+                Method::Lock,
+                Method::Unlock,
+                Method::Patch,
+            ]
+        );
     }
 
     #[test]
@@ -134,6 +134,7 @@ impl FromStr for AuthFile {
                 Method::Trace,
                 Method::Head,
                 Method::Post,
+                Method::Propfind,
             ];
 
             let mut users = Vec::new();
@@ -163,6 +164,15 @@ impl FromStr for AuthFile {
                     match line.to_lowercase().as_str() {
                         "allow-put"    => allows.push(Method::Put),
                         "allow-delete" => allows.push(Method::Delete),
+                        "allow-patch"  => allows.push(Method::Patch),
+                        "allow-mkcol"  => allows.push(Method::Mkcol),
+                        "allow-copy"   => allows.push(Method::Copy),
+                        "allow-move"   => allows.push(Method::Move),
+                        "allow-proppatch" => allows.push(Method::Proppatch),
+                        "allow-lock"   => {
+                            allows.push(Method::Lock);
+                            allows.push(Method::Unlock);
+                        }
                         _ => {
                             return Err(AuthFileParseError::UnrecognizedSymbol(
                                 line.into(),
@@ -32,9 +32,9 @@ impl SuppliedAuth {
         match self {
             SuppliedAuth::Basic{auth} => {
                 let decoded: Vec<u8> = base64::decode(&auth)
-                    .unwrap();
+                    .unwrap_or_default();
                 let decoded = String::from_utf8(decoded)
-                    .unwrap();
+                    .unwrap_or_default();
                 let username = &decoded[0..decoded.find(":").unwrap_or(0)];
 
                 (
@@ -0,0 +1,139 @@
+use crate::settings::Hook;
+use crate::webserver::requests::Request;
+use crate::webserver::shared::request_id;
+use crate::CONFIG;
+
+use is_executable::IsExecutable;
+use log::*;
+use regex::Regex;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+///How often a running hook is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+///Translates a glob into an anchored regex, where `*` and `?` match
+///within a single directory and `**` matches across them.
+fn glob_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+pub fn matches(glob: &str, path: &str) -> bool {
+    Regex::new(&glob_regex(glob)).map_or(false, |regex| regex.is_match(path))
+}
+
+///Runs every hook whose glob matches the path of a request that
+///changed the file at `url`, each on its own thread so the response
+///isn't held up. `size` is the size of the file written or removed,
+///and the user is the one whose credentials an auth file checked.
+pub fn fire(req: &Request, url: &Path, size: u64, user: Option<&str>) {
+    let path = req.path.to_string_lossy();
+
+    for hook in CONFIG
+        .hooks
+        .commands
+        .iter()
+        .filter(|hook| matches(&hook.glob, &path))
+    {
+        let envs = vec![
+            ("HOOK_METHOD", req.method.to_string()),
+            ("HOOK_PATH", path.to_string()),
+            ("HOOK_FILE", url.display().to_string()),
+            ("HOOK_USER", user.unwrap_or_default().to_string()),
+            ("HOOK_SIZE", size.to_string()),
+        ];
+
+        //The hook outlives the request, but its log lines still belong to it.
+        let id = request_id::current();
+        std::thread::spawn(move || {
+            request_id::set(id);
+            run(hook, envs)
+        });
+    }
+}
+
+fn run(hook: &Hook, envs: Vec<(&str, String)>) {
+    let command = hook.command.display();
+    if !hook.command.is_executable() {
+        error!("hook '{}' is not an executable", command);
+        return;
+    }
+
+    trace!("running hook: '{}'", command);
+    let mut child = match Command::new(&hook.command)
+        .envs(envs)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            error!("failed to start hook '{}': '{}'", command, err);
+            return;
+        }
+    };
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                debug!("hook '{}' completed", command);
+                return;
+            }
+            Ok(Some(status)) => {
+                warn!("hook '{}' failed with {}", command, status);
+                return;
+            }
+            Ok(None) if started.elapsed() >= CONFIG.hooks.timeout => {
+                warn!(
+                    "hook '{}' timed out after {}ms, killing it",
+                    command,
+                    CONFIG.hooks.timeout.as_millis()
+                );
+                if let Err(err) = child.kill().and_then(|_| child.wait()) {
+                    error!("failed to kill hook '{}': '{}'", command, err);
+                }
+                return;
+            }
+            Ok(None) => std::thread::sleep(POLL_INTERVAL),
+            Err(err) => {
+                error!("failed to wait on hook '{}': '{}'", command, err);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/site/*.md", "/site/index.md"));
+        assert!(!matches("/site/*.md", "/site/posts/first.md"));
+        assert!(matches("/site/**.md", "/site/posts/first.md"));
+        assert!(matches("/photos/**", "/photos/2020/a.jpg"));
+        assert!(matches("/img/?.png", "/img/a.png"));
+        assert!(!matches("/img/?.png", "/img/ab.png"));
+        assert!(matches("/a+b/(c).txt", "/a+b/(c).txt"));
+        assert!(!matches("/site/*.md", "/site/index.md.bak"));
+    }
+}
@@ -0,0 +1,216 @@
+use super::versions;
+use crate::webserver::requests::Request;
+use crate::webserver::responses::*;
+use crate::webserver::shared::headers::*;
+
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+
+pub const LINK_FORMAT: &str = "application/link-format";
+
+///A prior (or the current) state of a resource, as RFC 7089 calls it.
+///The current state of the file has no version id.
+#[derive(Debug, Clone)]
+pub struct Memento {
+    pub datetime: DateTime<Utc>,
+    pub id:       Option<String>,
+    pub content:  PathBuf,
+}
+
+impl Memento {
+    fn uri(&self, original: &str) -> String {
+        match self.id {
+            Some(ref id) => format!("{}?version={}", original, id),
+            None => original.into(),
+        }
+    }
+}
+
+///Collects every memento of a file, oldest first: the kept versions
+///followed by the file as it is now.
+pub fn mementos(path: &Path) -> Vec<Memento> {
+    let mut ret: Vec<_> = versions::list(path)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ver| Memento {
+            datetime: ver.modified.unwrap_or(ver.time),
+            content:  versions::content_path(path, &ver),
+            id:       Some(ver.id),
+        })
+        .collect();
+
+    if let Ok(modified) = path.metadata().and_then(|meta| meta.modified()) {
+        ret.push(Memento {
+            datetime: modified.into(),
+            id:       None,
+            content:  path.into(),
+        });
+    }
+
+    ret
+}
+
+///The Link header pointing back at the original resource, which is its
+///own TimeGate, and at its TimeMap.
+pub fn links(original: &str) -> String {
+    format!(
+        "<{0}>; rel=\"original timegate\", <{0}?timemap>; rel=\"timemap\"; \
+         type=\"{1}\"",
+        original, LINK_FORMAT
+    )
+}
+
+///Sets the headers every response from the original resource or one of
+///its mementos carries.
+pub fn memento_headers(
+    headers: &mut HeaderList,
+    original: &str,
+    datetime: Option<&DateTime<Utc>>,
+) {
+    if let Some(datetime) = datetime {
+        headers.memento_datetime(datetime);
+    }
+    headers.link(links(original));
+}
+
+///Builds the TimeMap of a resource in the link format of RFC 6690.
+pub fn timemap(original: &str, mementos: &[Memento]) -> String {
+    let mut entries = vec![
+        format!("<{}>; rel=\"original\"", original),
+        format!("<{}>; rel=\"timegate\"", original),
+        format!(
+            "<{}?timemap>; rel=\"self\"; type=\"{}\"",
+            original, LINK_FORMAT
+        ),
+    ];
+
+    for (i, memento) in mementos.iter().enumerate() {
+        let mut rel = String::from("memento");
+        if i == 0 {
+            rel.insert_str(0, "first ");
+        }
+        if i + 1 == mementos.len() {
+            rel.insert_str(0, "last ");
+        }
+
+        entries.push(format!(
+            "<{}>; rel=\"{}\"; datetime=\"{}\"",
+            memento.uri(original),
+            rel,
+            HeaderList::format_date(&memento.datetime)
+        ));
+    }
+
+    entries.join(",\n") + "\n"
+}
+
+///Answers a `?timemap` query with the TimeMap of the file.
+pub fn timemap_response(req: &Request, path: &Path) -> Response {
+    let mementos = mementos(path);
+    if mementos.is_empty() {
+        return Response::not_found();
+    }
+
+    let original = req.path.display().to_string();
+    let body = timemap(&original, &mementos).into_bytes();
+
+    let mut headers = HeaderList::response_headers();
+    headers.content(LINK_FORMAT, None, body.len());
+    memento_headers(&mut headers, &original, None);
+
+    Response {
+        code: StatusCode::Ok,
+        headers,
+        data: Some(body.into()),
+    }
+}
+
+///Acts as the TimeGate of the file, serving the memento closest to the
+///datetime the client asked for. The memento is served directly with
+///its own location in Content-Location, rather than redirecting.
+pub fn timegate_response(req: &Request, path: &Path) -> Option<Response> {
+    let candidates = mementos(path)
+        .into_iter()
+        .map(|memento| (memento.datetime, memento))
+        .collect();
+
+    let (_, memento) =
+        ContentNegotiator::new(path, &req.headers).best_datetime(candidates)?;
+
+    let original = req.path.display().to_string();
+    let mut resp = Response::file_response_as(path, &memento.content);
+
+    if resp.code == StatusCode::Ok {
+        memento_headers(&mut resp.headers, &original, Some(&memento.datetime));
+        resp.headers.content_location(memento.uri(&original));
+        resp.headers.vary(ACCEPT_DATETIME);
+    }
+
+    Some(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn memento(year: i32, id: Option<&str>) -> Memento {
+        Memento {
+            datetime: Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap(),
+            id:       id.map(String::from),
+            content:  PathBuf::new(),
+        }
+    }
+
+    #[test]
+    fn test_select_memento() {
+        let list = vec![
+            memento(2010, Some("a")),
+            memento(2015, Some("b")),
+            memento(2020, None),
+        ];
+
+        let pick = |date: &str| {
+            let headers: HeaderList =
+                format!("Accept-Datetime: {}", date).parse().unwrap();
+            let candidates = list
+                .iter()
+                .map(|memento| (memento.datetime, memento.id.clone()))
+                .collect();
+
+            ContentNegotiator::new(Path::new(""), &headers)
+                .best_datetime(candidates)
+                .map(|(_, id)| id)
+        };
+
+        assert_eq!(
+            pick("Fri, 01 Jan 2016 00:00:00 GMT"),
+            Some(Some("b".into()))
+        );
+        assert_eq!(
+            pick("Thu, 01 Jan 2015 00:00:00 GMT"),
+            Some(Some("b".into()))
+        );
+        assert_eq!(
+            pick("Sat, 01 Jan 2000 00:00:00 GMT"),
+            Some(Some("a".into()))
+        );
+        assert_eq!(pick("Sun, 01 Jan 2040 00:00:00 GMT"), Some(None));
+        assert_eq!(pick("yesterday"), None);
+    }
+
+    #[test]
+    fn test_timemap() {
+        let list = vec![memento(2010, Some("a")), memento(2020, None)];
+
+        assert_eq!(
+            timemap("/f.txt", &list),
+            "</f.txt>; rel=\"original\",\n</f.txt>; \
+             rel=\"timegate\",\n</f.txt?timemap>; rel=\"self\"; \
+             type=\"application/link-format\",\n</f.txt?version=a>; \
+             rel=\"first memento\"; datetime=\"Fri, 01 Jan 2010 00:00:00 \
+             GMT\",\n</f.txt>; rel=\"last memento\"; datetime=\"Wed, 01 Jan \
+             2020 00:00:00 GMT\"\n"
+        );
+    }
+}
@@ -0,0 +1,494 @@
+use super::quota;
+use super::removal;
+use super::upload;
+use super::versions;
+use super::webdav::locks;
+use crate::webserver::requests::Request;
+use crate::CONFIG;
+
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+///The most bytes the headers of a single part may take up.
+const MAX_PART_HEADERS: usize = 8192;
+
+#[derive(Debug)]
+pub enum MultipartError {
+    IoError(std::io::Error),
+    Truncated,
+    Malformed(&'static str),
+}
+
+impl Display for MultipartError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        use MultipartError::*;
+
+        match self {
+            IoError(err) => write!(fmt, "{}", err),
+            Truncated => write!(fmt, "body ended before the last boundary"),
+            Malformed(what) => {
+                write!(fmt, "malformed multipart body: {}", what)
+            }
+        }
+    }
+}
+
+impl Error for MultipartError {}
+
+impl From<std::io::Error> for MultipartError {
+    fn from(err: std::io::Error) -> Self { MultipartError::IoError(err) }
+}
+
+type Result<T> = std::result::Result<T, MultipartError>;
+
+///Pulls the boundary out of a multipart/form-data content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let typ = params.next()?.trim();
+    if !typ.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| {
+            let mut split = param.splitn(2, '=');
+            Some((split.next()?.trim(), split.next()?.trim()))
+        })
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, val)| val.trim_matches('"').to_string())
+        .filter(|val| !val.is_empty() && val.len() <= 70)
+}
+
+///Turns the filename a client sent into one that is safe to create
+///inside the upload directory. Any path the client included is
+///dropped, along with control characters and leading dots.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(&['/', '\\'][..]).next()?;
+    let name: String = name
+        .chars()
+        .filter(|chr| !chr.is_control())
+        .collect::<String>()
+        .trim()
+        .trim_start_matches('.')
+        .chars()
+        .take(255)
+        .collect();
+
+    if name.is_empty() || name == CONFIG.auth.file_name {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+///The headers of a part that matter for an upload.
+#[derive(Debug, Default, PartialEq)]
+pub struct PartInfo {
+    pub name:     String,
+    pub filename: Option<String>,
+}
+
+impl PartInfo {
+    fn parse(head: &str) -> Result<Self> {
+        let disposition = head
+            .lines()
+            .filter_map(|line| {
+                let mut split = line.splitn(2, ':');
+                Some((split.next()?.trim(), split.next()?.trim()))
+            })
+            .find(|(key, _)| key.eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, val)| val)
+            .ok_or(MultipartError::Malformed("part without a disposition"))?;
+
+        let mut info = PartInfo::default();
+        for param in disposition.split(';').skip(1) {
+            let mut split = param.splitn(2, '=');
+            let key = split.next().unwrap_or_default().trim().to_lowercase();
+            let val = split.next().unwrap_or_default().trim().trim_matches('"');
+
+            match key.as_str() {
+                "name" => info.name = val.into(),
+                "filename" => info.filename = Some(val.into()),
+                _ => (),
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+///Reads the parts of a multipart body one at a time. The body has been
+///read into memory along with the rest of the request by then, within
+///the limit on request bodies, and the upload limits decide what of it
+///gets saved.
+pub struct Multipart<R> {
+    reader:  R,
+    delim:   Vec<u8>,
+    buf:     Vec<u8>,
+    in_part: bool,
+    done:    bool,
+}
+
+impl<R: BufRead> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            delim: format!("\r\n--{}", boundary).into_bytes(),
+            //The first boundary isn't preceded by a line break, so one is
+            //made up to have every delimiter look the same.
+            buf: b"\r\n".to_vec(),
+            //The preamble is read like a part nobody asked for.
+            in_part: true,
+            done: false,
+        }
+    }
+
+    fn fill(&mut self) -> Result<bool> {
+        let len = {
+            let data = self.reader.fill_buf()?;
+            self.buf.extend_from_slice(data);
+            data.len()
+        };
+        self.reader.consume(len);
+
+        Ok(len != 0)
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buf.windows(needle.len()).position(|win| win == needle)
+    }
+
+    ///Copies the rest of the current part into the writer, up to
+    ///`limit` bytes. Anything past the limit is read and thrown away,
+    ///and the returned size says how big the part really was.
+    pub fn copy_part<W: Write>(
+        &mut self,
+        out: &mut W,
+        limit: u64,
+    ) -> Result<u64> {
+        let keep = self.delim.len() - 1;
+        let mut total = 0;
+
+        while self.in_part {
+            let (end, found) = match self.find(&self.delim) {
+                Some(pos) => (pos, true),
+                None => (self.buf.len().saturating_sub(keep), false),
+            };
+
+            if total < limit {
+                let room = (limit - total).min(end as u64) as usize;
+                out.write_all(&self.buf[..room])?;
+            }
+            total += end as u64;
+
+            if found {
+                self.buf.drain(..end + self.delim.len());
+                self.in_part = false;
+            } else {
+                self.buf.drain(..end);
+                if !self.fill()? {
+                    return Err(MultipartError::Truncated);
+                }
+            }
+        }
+
+        Ok(total)
+    }
+
+    ///Moves on to the next part, returning its headers, or None after
+    ///the closing boundary.
+    pub fn next_part(&mut self) -> Result<Option<PartInfo>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        //Skip whatever is left of the previous part.
+        self.copy_part(&mut std::io::sink(), 0)?;
+
+        while self.buf.len() < 2 {
+            if !self.fill()? {
+                return Err(MultipartError::Truncated);
+            }
+        }
+
+        if self.buf.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        let end = loop {
+            if let Some(pos) = self.find(b"\r\n\r\n") {
+                break pos;
+            }
+            if self.buf.len() > MAX_PART_HEADERS {
+                return Err(MultipartError::Malformed("part headers too long"));
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Truncated);
+            }
+        };
+
+        //The headers start after the line break ending the boundary.
+        let head = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+        self.buf.drain(..end + 4);
+        self.in_part = true;
+
+        PartInfo::parse(&head).map(Some)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadedFile {
+    pub field: String,
+    pub name:  String,
+    pub size:  u64,
+    pub saved: bool,
+    pub error: Option<String>,
+}
+
+///What became of each file in an upload, for the response.
+#[derive(Debug, Serialize)]
+pub struct UploadSummary {
+    pub dir:        String,
+    pub files:      Vec<UploadedFile>,
+    #[serde(skip)]
+    pub over_quota: bool,
+    #[serde(skip)]
+    pub locked:     bool,
+}
+
+impl UploadSummary {
+    pub fn saved(&self) -> usize {
+        self.files.iter().filter(|file| file.saved).count()
+    }
+}
+
+///Why a file in an upload wasn't saved.
+enum Refusal {
+    Invalid(String),
+    OverQuota(quota::QuotaExceeded),
+    Locked,
+}
+
+impl From<&str> for Refusal {
+    fn from(reason: &str) -> Self { Refusal::Invalid(reason.into()) }
+}
+
+///Saves a single file part into the directory, through a temporary
+///file so a part that turns out too large never replaces anything.
+fn save_part<R: BufRead>(
+    parts: &mut Multipart<R>,
+    dest: &Path,
+    req: &Request,
+    user: Option<&str>,
+) -> Result<std::result::Result<u64, Refusal>> {
+    let limit = CONFIG.uploads.max_file_size;
+    let temp = upload::temp_path(dest);
+
+    let res = File::create(&temp).map_err(MultipartError::from).and_then(
+        |mut file| {
+            let size = parts.copy_part(&mut file, limit)?;
+            file.sync_all()?;
+            Ok(size)
+        },
+    );
+
+    let size = match res {
+        Ok(size) if size <= limit => size,
+        Ok(size) => {
+            let _ = std::fs::remove_file(&temp);
+            return Ok(Err(Refusal::Invalid(format!(
+                "file is {} bytes, over the limit of {}",
+                size, limit
+            ))));
+        }
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp);
+            return Err(err);
+        }
+    };
+
+    let before = quota::size_of(dest);
+    let grow = size.saturating_sub(before.unwrap_or(0));
+    let reservation = match quota::reserve(dest, user, grow, before.is_none()) {
+        Ok(reservation) => reservation,
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp);
+            return Ok(Err(Refusal::OverQuota(err)));
+        }
+    };
+
+    let res = versions::keep(dest, req, user)
+        .and_then(|_| std::fs::rename(&temp, dest));
+    if let Err(err) = res {
+        let _ = std::fs::remove_file(&temp);
+        log::warn!("failed to save upload '{}': '{}'", dest.display(), err);
+        return Ok(Err("file could not be saved".into()));
+    }
+
+    reservation.record(dest, user, before);
+    Ok(Ok(size))
+}
+
+///Saves every file part of a multipart body into the directory. Parts
+///that aren't files are skipped, and files that can't be saved are
+///reported in the summary rather than failing the whole upload.
+pub fn save_uploads<R: BufRead>(
+    reader: R,
+    boundary: &str,
+    dir: &Path,
+    req: &Request,
+    user: Option<&str>,
+) -> Result<UploadSummary> {
+    let mut parts = Multipart::new(reader, boundary);
+    let mut summary = UploadSummary {
+        dir:        req.path.to_string_lossy().into(),
+        files:      Vec::new(),
+        over_quota: false,
+        locked:     false,
+    };
+    //There's no single target for the dispatcher to check locks on, so
+    //each file is checked as its name becomes known.
+    let tokens = locks::submitted_tokens(&req.headers);
+
+    while let Some(part) = parts.next_part()? {
+        let original = match part.filename {
+            Some(ref name) if !name.is_empty() => name.clone(),
+            _ => continue,
+        };
+
+        let mut file = UploadedFile {
+            field: part.name,
+            name:  original.clone(),
+            size:  0,
+            saved: false,
+            error: None,
+        };
+
+        let dest = sanitize_filename(&original).map(|name| dir.join(name));
+        let res = match dest {
+            _ if summary.files.len() >= CONFIG.uploads.max_files => {
+                Err("too many files in one upload".into())
+            }
+            Some(ref dest) if dest.is_dir() || removal::is_protected(dest) => {
+                Err("name is not available".into())
+            }
+            Some(ref dest) if locks::blocked(dest, false, &tokens) => {
+                Err(Refusal::Locked)
+            }
+            Some(ref dest) => {
+                file.name = dest
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into())
+                    .unwrap_or_default();
+                save_part(&mut parts, dest, req, user)?
+            }
+            None => Err("invalid filename".into()),
+        };
+
+        match res {
+            Ok(size) => {
+                file.size = size;
+                file.saved = true;
+            }
+            Err(Refusal::Invalid(reason)) => file.error = Some(reason),
+            Err(Refusal::OverQuota(err)) => {
+                file.error = Some(err.to_string());
+                summary.over_quota = true;
+            }
+            Err(Refusal::Locked) => {
+                file.error = Some("file is locked".into());
+                summary.locked = true;
+            }
+        }
+        summary.files.push(file);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"abc def\""),
+            Some("abc def".into())
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data;charset=utf-8;boundary=x1"),
+            Some("x1".into())
+        );
+        assert_eq!(boundary("multipart/mixed; boundary=x1"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("notes.txt"), Some("notes.txt".into()));
+        assert_eq!(
+            sanitize_filename("../../etc/passwd"),
+            Some("passwd".into())
+        );
+        assert_eq!(sanitize_filename("C:\\tmp\\a.doc"), Some("a.doc".into()));
+        assert_eq!(sanitize_filename(".htaccess"), Some("htaccess".into()));
+        assert_eq!(sanitize_filename("a\r\nb"), Some("ab".into()));
+        assert_eq!(sanitize_filename(".."), None);
+        assert_eq!(sanitize_filename("dir/"), None);
+        assert_eq!(sanitize_filename(&CONFIG.auth.file_name), None);
+    }
+
+    #[test]
+    fn test_multipart() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line one --XyZ\r\nline two\r\n--XyZ--\r\n";
+
+        //A tiny buffer makes the delimiter straddle reads.
+        let reader = std::io::BufReader::with_capacity(3, &body[..]);
+        let mut parts = Multipart::new(reader, "XyZ");
+
+        let part = parts.next_part().unwrap().unwrap();
+        assert_eq!(part.name, "title");
+        assert_eq!(part.filename, None);
+
+        let part = parts.next_part().unwrap().unwrap();
+        assert_eq!(part.filename, Some("a.txt".into()));
+
+        let mut out = Vec::new();
+        assert_eq!(parts.copy_part(&mut out, 1024).unwrap(), 24);
+        assert_eq!(out, b"line one --XyZ\r\nline two");
+
+        assert!(parts.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_multipart_limit() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\n\
+            0123456789\r\n--b--";
+        let mut parts = Multipart::new(&body[..], "b");
+        parts.next_part().unwrap().unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(parts.copy_part(&mut out, 4).unwrap(), 10);
+        assert_eq!(out, b"0123");
+
+        let truncated =
+            b"--b\r\nContent-Disposition: form-data; name=\"f\"\r\n\r\nabc";
+        let mut parts = Multipart::new(&truncated[..], "b");
+        parts.next_part().unwrap().unwrap();
+        match parts.copy_part(&mut std::io::sink(), 10) {
+            Err(MultipartError::Truncated) => (),
+            res => panic!("expected a truncated body: {:?}", res),
+        }
+    }
+}
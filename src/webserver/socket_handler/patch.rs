@@ -0,0 +1,211 @@
+use super::upload::{self, UploadError};
+use crate::webserver::shared::headers::*;
+
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::Path;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+pub const PLAIN_TEXT: &str = "text/plain";
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+
+///The patch formats accepted, as advertised in Accept-Patch.
+pub static PATCH_TYPES: &[&str] = &[OCTET_STREAM, PLAIN_TEXT, MERGE_PATCH];
+
+#[derive(Debug)]
+pub enum PatchError {
+    UploadError(UploadError),
+    UnsupportedType(String),
+    NotJson(String),
+    InvalidPatch(serde_json::Error),
+    InvalidRange(String),
+}
+
+impl Display for PatchError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        use PatchError::*;
+
+        match self {
+            UploadError(err) => write!(fmt, "{}", err),
+            UnsupportedType(typ) => {
+                write!(fmt, "patch format '{}' is not supported here", typ)
+            }
+            NotJson(err) => write!(fmt, "target is not valid JSON: {}", err),
+            InvalidPatch(err) => write!(fmt, "invalid merge patch: {}", err),
+            InvalidRange(range) => {
+                write!(fmt, "invalid content range '{}'", range)
+            }
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+impl From<UploadError> for PatchError {
+    fn from(err: UploadError) -> Self { PatchError::UploadError(err) }
+}
+
+impl From<std::io::Error> for PatchError {
+    fn from(err: std::io::Error) -> Self {
+        PatchError::UploadError(UploadError::IoError(err))
+    }
+}
+
+type Result<T> = std::result::Result<T, PatchError>;
+
+///Applies a JSON Merge Patch (RFC 7396) to a value.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    if let Value::Object(target) = target {
+        for (key, val) in patch {
+            if val.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.as_str()).or_insert(Value::Null), val);
+            }
+        }
+    }
+}
+
+fn merge_file(path: &Path, payload: &[u8]) -> Result<()> {
+    if path.extension().map_or(true, |ext| ext != "json") {
+        return Err(PatchError::UnsupportedType(MERGE_PATCH.into()));
+    }
+
+    let patch: Value =
+        serde_json::from_slice(payload).map_err(PatchError::InvalidPatch)?;
+
+    let mut target = if path.is_file() {
+        let current = std::fs::read(path)?;
+        serde_json::from_slice(&current)
+            .map_err(|err| PatchError::NotJson(err.to_string()))?
+    } else {
+        Value::Null
+    };
+
+    merge(&mut target, &patch);
+
+    let mut merged = serde_json::to_vec_pretty(&target)
+        .map_err(|err| PatchError::NotJson(err.to_string()))?;
+    merged.push(b'\n');
+
+    Ok(upload::write_atomic(path, &merged, None)?)
+}
+
+///The format of a PATCH, from its content type.
+fn patch_type(headers: &HeaderList) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|typ| typ.split(';').next())
+        .map(|typ| typ.trim().to_lowercase())
+        .unwrap_or_else(|| OCTET_STREAM.into())
+}
+
+///Whether a PATCH only appends to the end of the file.
+pub fn is_append(headers: &HeaderList) -> bool {
+    let typ = patch_type(headers);
+
+    (typ == OCTET_STREAM || typ == PLAIN_TEXT) && !headers.has(CONTENT_RANGE)
+}
+
+///Applies the payload of a PATCH to the file, in the format given by
+///its content type. Byte patches need a Content-Range and change the
+///file in place, while octet streams without one, or plain text, are
+///appended to the end of it.
+pub fn apply(path: &Path, headers: &HeaderList, payload: &[u8]) -> Result<()> {
+    let typ = patch_type(headers);
+
+    let range = match headers.get(CONTENT_RANGE) {
+        Some(range) => Some(
+            range
+                .parse::<ContentRange>()
+                .map_err(|_| PatchError::InvalidRange(range.into()))?,
+        ),
+        None => None,
+    };
+
+    match (typ.as_str(), range) {
+        (OCTET_STREAM, Some(range)) => {
+            Ok(upload::write_atomic(path, payload, Some(&range))?)
+        }
+        (OCTET_STREAM, None) | (PLAIN_TEXT, None) => {
+            Ok(upload::append(path, payload)?)
+        }
+        (MERGE_PATCH, None) => merge_file(path, payload),
+        _ => Err(PatchError::UnsupportedType(typ)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge() {
+        let cases = vec![
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+
+        for (mut target, patch, expected) in cases {
+            merge(&mut target, &patch);
+            assert_eq!(target, expected, "patch {}", patch);
+        }
+    }
+
+    #[test]
+    fn test_is_append() {
+        let headers = |raw: &str| raw.parse::<HeaderList>().unwrap();
+
+        assert!(is_append(&HeaderList::default()));
+        assert!(is_append(&headers(
+            "Content-Type: text/plain; charset=utf-8"
+        )));
+        assert!(!is_append(&headers("Content-Range: bytes 0-1/*")));
+        assert!(!is_append(&headers(
+            "Content-Type: application/merge-patch+json"
+        )));
+    }
+}
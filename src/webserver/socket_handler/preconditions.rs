@@ -0,0 +1,252 @@
+use super::etag::*;
+use crate::webserver::shared::*;
+
+use chrono::{DateTime, Utc};
+use std::path::Path;
+
+///The parts of a resource's current state that preconditions are
+///evaluated against.
+#[derive(Debug, Default)]
+pub struct ResourceState {
+    pub exists:   bool,
+    pub etag:     Option<String>,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+impl ResourceState {
+    pub fn of(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+
+        let etag = if path.is_dir() {
+            dir_etag(path)
+        } else {
+            file_etag(path)
+        };
+
+        Self {
+            exists:   true,
+            etag:     etag.ok(),
+            modified: path
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .map(|modified| modified.into()),
+        }
+    }
+}
+
+///What should happen to a request once its preconditions have been
+///evaluated.
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+///A single entity tag out of an If-Match or If-None-Match list.
+struct EntityTag<'a> {
+    weak:   bool,
+    opaque: &'a str,
+}
+
+impl<'a> EntityTag<'a> {
+    fn parse(tag: &'a str) -> Self {
+        let tag = tag.trim();
+
+        if tag.starts_with("W/") {
+            Self {
+                weak:   true,
+                opaque: &tag[2..],
+            }
+        } else {
+            Self {
+                weak:   false,
+                opaque: tag,
+            }
+        }
+    }
+
+    ///Compares the opaque parts of the tags. A tag handed out for a
+    ///compressed variant still refers to the same resource, so its
+    ///coding suffix is ignored.
+    fn same_opaque(&self, other: &EntityTag) -> bool {
+        if self.opaque == other.opaque {
+            return true;
+        }
+
+        let base = other.opaque.trim_end_matches('"');
+        let variant = self.opaque.trim_end_matches('"');
+
+        [encoding::GZIP, encoding::DEFLATE, encoding::BROTLI]
+            .iter()
+            .any(|coding| variant == format!("{}-{}", base, coding))
+    }
+}
+
+fn strong_eq(a: &EntityTag, b: &EntityTag) -> bool {
+    !a.weak && !b.weak && a.same_opaque(b)
+}
+
+fn weak_eq(a: &EntityTag, b: &EntityTag) -> bool { a.same_opaque(b) }
+
+///Checks an If-Match or If-None-Match value against the current etag
+///of the resource, using the given comparison.
+fn list_matches(
+    list: &str,
+    state: &ResourceState,
+    compare: fn(&EntityTag, &EntityTag) -> bool,
+) -> bool {
+    if list.trim() == "*" {
+        return state.exists;
+    }
+
+    let current = match state.etag {
+        Some(ref etag) => EntityTag::parse(etag),
+        None => return false,
+    };
+
+    list.split(",")
+        .map(EntityTag::parse)
+        .any(|tag| compare(&tag, &current))
+}
+
+///Dates in headers only have second precision, so the modification
+///time is truncated before comparing.
+fn modified_after(state: &ResourceState, date: DateTime<Utc>) -> bool {
+    match state.modified {
+        Some(modified) => modified.timestamp() > date.timestamp(),
+        None => false,
+    }
+}
+
+///Evaluates the conditional headers of a request in the order given
+///by RFC 7232 section 6.
+pub fn evaluate(
+    method: &Method,
+    headers: &HeaderList,
+    state: &ResourceState,
+) -> Precondition {
+    use Precondition::*;
+
+    let safe = *method == Method::Get || *method == Method::Head;
+
+    if let Some(list) = headers.get(IF_MATCH) {
+        if !list_matches(list, state, strong_eq) {
+            return Failed;
+        }
+    } else if let Some(date) = headers.get_date(IF_UNMODIFIED_SINCE) {
+        if state.exists && modified_after(state, date) {
+            return Failed;
+        }
+    }
+
+    if let Some(list) = headers.get(IF_NONE_MATCH) {
+        if list_matches(list, state, weak_eq) {
+            return if safe { NotModified } else { Failed };
+        }
+    } else if let Some(date) = headers.get_date(IF_MODIFIED_SINCE) {
+        //A date later than now can't be from a response we sent, so
+        //it's ignored as RFC 7232 section 3.3 asks.
+        if safe
+            && state.exists
+            && date <= Utc::now()
+            && !modified_after(state, date)
+        {
+            return NotModified;
+        }
+    }
+
+    Proceed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use Precondition::*;
+
+    const OLDER: &str = "Sat, 01 Jan 2000 00:00:00 GMT";
+    const SAME: &str = "Mon, 01 Jan 2018 00:00:00 GMT";
+    const LATER: &str = "Wed, 01 Jan 2025 00:00:00 GMT";
+    const NEWER: &str = "Sun, 01 Jan 2040 00:00:00 GMT";
+
+    fn existing() -> ResourceState {
+        ResourceState {
+            exists:   true,
+            etag:     Some("\"abc\"".into()),
+            modified: Some(Utc.with_ymd_and_hms(2018, 1, 1, 0, 0, 0).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let missing = ResourceState::default();
+
+        let table: &[(Method, &str, &ResourceState, Precondition)] = &[
+            (Method::Get, "", &existing(), Proceed),
+            //If-Match uses the strong comparison
+            (Method::Get, "If-Match: \"abc\"", &existing(), Proceed),
+            (Method::Get, "If-Match: \"x\", \"abc\"", &existing(), Proceed),
+            (Method::Get, "If-Match: \"x\"", &existing(), Failed),
+            (Method::Get, "If-Match: W/\"abc\"", &existing(), Failed),
+            (Method::Get, "If-Match: *", &existing(), Proceed),
+            (Method::Get, "If-Match: *", &missing, Failed),
+            (Method::Put, "If-Match: \"abc\"", &missing, Failed),
+            //If-Unmodified-Since only counts without If-Match
+            (Method::Get, &format!("If-Unmodified-Since: {}", OLDER), &existing(), Failed),
+            (Method::Get, &format!("If-Unmodified-Since: {}", SAME), &existing(), Proceed),
+            (Method::Get, &format!("If-Unmodified-Since: {}", NEWER), &existing(), Proceed),
+            (
+                Method::Get,
+                &format!("If-Match: \"abc\"\r\nIf-Unmodified-Since: {}", OLDER),
+                &existing(),
+                Proceed,
+            ),
+            //If-None-Match uses the weak comparison
+            (Method::Get, "If-None-Match: \"abc\"", &existing(), NotModified),
+            (Method::Head, "If-None-Match: W/\"abc\"", &existing(), NotModified),
+            (Method::Get, "If-None-Match: \"abc-gzip\"", &existing(), NotModified),
+            (Method::Get, "If-None-Match: \"x\"", &existing(), Proceed),
+            (Method::Get, "If-None-Match: *", &existing(), NotModified),
+            (Method::Get, "If-None-Match: *", &missing, Proceed),
+            (Method::Put, "If-None-Match: *", &existing(), Failed),
+            (Method::Put, "If-None-Match: *", &missing, Proceed),
+            (Method::Delete, "If-None-Match: \"abc\"", &existing(), Failed),
+            //If-Modified-Since only counts without If-None-Match
+            (Method::Get, &format!("If-Modified-Since: {}", OLDER), &existing(), Proceed),
+            (Method::Get, &format!("If-Modified-Since: {}", SAME), &existing(), NotModified),
+            (Method::Get, &format!("If-Modified-Since: {}", LATER), &existing(), NotModified),
+            //Dates in the future are ignored
+            (Method::Get, &format!("If-Modified-Since: {}", NEWER), &existing(), Proceed),
+            (Method::Put, &format!("If-Modified-Since: {}", NEWER), &existing(), Proceed),
+            (
+                Method::Get,
+                &format!("If-None-Match: \"x\"\r\nIf-Modified-Since: {}", NEWER),
+                &existing(),
+                Proceed,
+            ),
+            //If-Match is evaluated before If-None-Match
+            (
+                Method::Get,
+                "If-Match: \"x\"\r\nIf-None-Match: \"abc\"",
+                &existing(),
+                Failed,
+            ),
+        ];
+
+        for (method, headers, state, expected) in table.iter() {
+            let headers: HeaderList = headers.parse().unwrap();
+
+            assert_eq!(
+                evaluate(method, &headers, state),
+                *expected,
+                "{} with {:?}",
+                method,
+                headers
+            );
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use super::versions;
+use crate::settings::UserQuota;
+use crate::CONFIG;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref LEDGER: Mutex<Ledger> = Mutex::new(Ledger::load());
+}
+
+///How long changes to the owners wait before the ledger file is written,
+///so a burst of writes only saves it once.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+static SAVER: Once = Once::new();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    fn grow(&mut self, by: Usage) {
+        self.bytes += by.bytes;
+        self.files += by.files;
+    }
+
+    fn shrink(&mut self, by: Usage) {
+        self.bytes = self.bytes.saturating_sub(by.bytes);
+        self.files = self.files.saturating_sub(by.files);
+    }
+
+    ///Moves the usage from a file being `before` bytes to it being
+    ///`after` bytes, where None means it doesn't exist.
+    fn apply(&mut self, before: Option<u64>, after: Option<u64>) {
+        self.bytes = (self.bytes + after.unwrap_or(0))
+            .saturating_sub(before.unwrap_or(0));
+
+        match (before, after) {
+            (None, Some(_)) => self.files += 1,
+            (Some(_), None) => self.files = self.files.saturating_sub(1),
+            _ => (),
+        }
+    }
+
+    ///Checks that growing by `bytes` and `files` stays within the
+    ///limits, where a limit of 0 means no limit.
+    fn fits(
+        &self,
+        bytes: u64,
+        files: u64,
+        max_bytes: u64,
+        max_files: u64,
+    ) -> Option<(&'static str, u64)> {
+        if max_bytes != 0 && bytes > 0 && self.bytes + bytes > max_bytes {
+            Some(("bytes", max_bytes))
+        } else if max_files != 0 && files > 0 && self.files + files > max_files
+        {
+            Some(("files", max_files))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub scope: String,
+    pub unit:  &'static str,
+    pub max:   u64,
+}
+
+impl Display for QuotaExceeded {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "the quota of {} {} for {} would be exceeded",
+            self.max, self.unit, self.scope
+        )
+    }
+}
+
+impl Error for QuotaExceeded {}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Owned {
+    user: String,
+    size: u64,
+}
+
+///The usage counted against each quota. Directories are scanned the
+///first time a quota needs them and kept up to date from then on,
+///while the owner of every file is kept in the ledger file so user
+///usage survives restarts. Usage reserved by writes still under way
+///is kept apart until they're recorded.
+#[derive(Default)]
+struct Ledger {
+    dirs:          HashMap<PathBuf, (Usage, u64)>,
+    owners:        HashMap<PathBuf, Owned>,
+    users:         HashMap<String, Usage>,
+    pending_dirs:  HashMap<PathBuf, Usage>,
+    pending_users: HashMap<String, Usage>,
+    scans:         u64,
+    dirty:         bool,
+}
+
+impl Ledger {
+    fn load() -> Self {
+        let mut ledger = Ledger::default();
+        if CONFIG.quotas.users.is_empty() {
+            return ledger;
+        }
+
+        match std::fs::read(&CONFIG.quotas.ledger) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(owners) => ledger.owners = owners,
+                Err(err) => error!("ignoring bad quota ledger: '{}'", err),
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => error!("failed to read quota ledger: '{}'", err),
+        }
+
+        for owned in ledger.owners.values() {
+            ledger
+                .users
+                .entry(owned.user.clone())
+                .or_default()
+                .apply(None, Some(owned.size));
+        }
+
+        ledger
+    }
+
+    ///Marks the owners as changed, leaving the writing of the file to
+    ///the saver thread.
+    fn save(&mut self) {
+        self.dirty = true;
+        SAVER.call_once(|| {
+            std::thread::spawn(save_changes);
+        });
+    }
+
+    ///The usage of a directory quota along with the number of the scan
+    ///it came from.
+    fn dir_usage(&mut self, root: &Path) -> (Usage, u64) {
+        if let Some(scanned) = self.dirs.get(root) {
+            return *scanned;
+        }
+
+        let usage = usage_of(root);
+        debug!(
+            "'{}' holds {} files in {} bytes",
+            root.display(),
+            usage.files,
+            usage.bytes
+        );
+
+        self.scans += 1;
+        self.dirs.insert(root.into(), (usage, self.scans));
+        (usage, self.scans)
+    }
+
+    ///Drops the usage of every directory quota overlapping the path,
+    ///so they're scanned again the next time they're needed.
+    fn rescan(&mut self, path: &Path) {
+        self.dirs.retain(|root, _| {
+            !root.starts_with(path) && !path.starts_with(root)
+        });
+    }
+}
+
+///Writes the owners out whenever they changed. They're serialized under
+///the lock, but the file is written outside of it.
+fn save_changes() {
+    loop {
+        std::thread::sleep(SAVE_DELAY);
+
+        let data = {
+            let mut ledger = LEDGER.lock().unwrap();
+            if !ledger.dirty {
+                continue;
+            }
+
+            ledger.dirty = false;
+            serde_json::to_vec(&ledger.owners)
+        };
+
+        let res = data
+            .map_err(std::io::Error::from)
+            .and_then(|data| std::fs::write(&CONFIG.quotas.ledger, data));
+        if let Err(err) = res {
+            error!("failed to write quota ledger: '{}'", err);
+        }
+    }
+}
+
+///Visits every file beneath a directory with its size.
+fn scan(dir: &Path, visit: &mut dyn FnMut(&Path, u64)) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if versions::is_store_path(&path) {
+            continue;
+        }
+
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => scan(&path, visit),
+            Ok(meta) => visit(&path, meta.len()),
+            Err(_) => (),
+        }
+    }
+}
+
+///The usage of a file, or of everything beneath a directory.
+pub fn usage_of(path: &Path) -> Usage {
+    let mut usage = Usage::default();
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => {
+            scan(path, &mut |_, len| usage.apply(None, Some(len)))
+        }
+        Ok(meta) => usage.apply(None, Some(meta.len())),
+        Err(_) => (),
+    }
+
+    usage
+}
+
+pub fn enabled() -> bool {
+    !CONFIG.quotas.dirs.is_empty() || !CONFIG.quotas.users.is_empty()
+}
+
+///The size of the file at the path, or None when there is no file.
+pub fn size_of(path: &Path) -> Option<u64> {
+    path.metadata()
+        .ok()
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+}
+
+fn dir_root(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.into()
+    } else {
+        CONFIG.root.join(path)
+    }
+}
+
+fn user_quota(user: &str) -> Option<&'static UserQuota> {
+    let quotas = &CONFIG.quotas.users;
+
+    quotas
+        .iter()
+        .find(|quota| quota.user == user)
+        .or_else(|| quotas.iter().find(|quota| quota.user == "*"))
+}
+
+///The usage a passed quota check set aside, and what it was set aside
+///against.
+struct Reserved {
+    dirs: Vec<(PathBuf, u64)>,
+    user: Option<String>,
+    grow: Usage,
+}
+
+impl Reserved {
+    ///Gives the usage back, returning the directories scanned again in
+    ///the meantime, whose usage may already include the write.
+    fn release(self, ledger: &mut Ledger) -> Vec<PathBuf> {
+        for (root, _) in self.dirs.iter() {
+            if let Some(pending) = ledger.pending_dirs.get_mut(root) {
+                pending.shrink(self.grow);
+            }
+        }
+        if let Some(user) = self.user {
+            if let Some(pending) = ledger.pending_users.get_mut(&user) {
+                pending.shrink(self.grow);
+            }
+        }
+
+        self.dirs
+            .into_iter()
+            .filter(|(root, serial)| {
+                ledger
+                    .dirs
+                    .get(root)
+                    .map_or(false, |(_, scanned)| scanned != serial)
+            })
+            .map(|(root, _)| root)
+            .collect()
+    }
+}
+
+///A passed quota check. The usage it allowed counts against the quotas
+///until the write is recorded or the reservation dropped, so concurrent
+///writes can't all pass the same check, without holding on to the
+///ledger while the write happens.
+pub struct Reservation(Option<Reserved>);
+
+impl Reservation {
+    ///Records the write the reservation was made for, trading the
+    ///reserved usage for the real one.
+    pub fn record(
+        mut self,
+        path: &Path,
+        user: Option<&str>,
+        before: Option<u64>,
+    ) {
+        if let Some(reserved) = self.0.take() {
+            let mut ledger = LEDGER.lock().unwrap();
+            let stale = reserved.release(&mut ledger);
+            record_in(&mut ledger, path, user, before);
+            for root in stale {
+                ledger.dirs.remove(&root);
+            }
+        }
+    }
+
+    ///Records the copy or move the reservation was made for. Moved files
+    ///keep their owners, while copies belong to the user who made them.
+    pub fn moved(mut self, from: Option<&Path>, to: &Path, user: Option<&str>) {
+        if let Some(reserved) = self.0.take() {
+            let mut ledger = LEDGER.lock().unwrap();
+            reserved.release(&mut ledger);
+            moved_in(&mut ledger, from, to, user);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(reserved) = self.0.take() {
+            reserved.release(&mut LEDGER.lock().unwrap());
+        }
+    }
+}
+
+///Checks that growing the file at the path by `grow` bytes, and
+///creating it when `new_file` is set, keeps every quota covering it.
+///Bytes count against the user who first wrote the file.
+pub fn reserve(
+    path: &Path,
+    user: Option<&str>,
+    grow: u64,
+    new_file: bool,
+) -> Result<Reservation, QuotaExceeded> {
+    let grow = Usage {
+        bytes: grow,
+        files: new_file as u64,
+    };
+
+    reserve_usage(path, user, grow, None)
+}
+
+///Checks that copying or moving a tree with the given usage to `to`
+///keeps every quota covering it. Moves only count against directories
+///the tree wasn't already in, and never against users.
+pub fn reserve_tree(
+    from: &Path,
+    to: &Path,
+    user: Option<&str>,
+    usage: Usage,
+    moving: bool,
+) -> Result<Reservation, QuotaExceeded> {
+    reserve_usage(to, user, usage, if moving { Some(from) } else { None })
+}
+
+fn reserve_usage(
+    path: &Path,
+    user: Option<&str>,
+    grow: Usage,
+    moved_from: Option<&Path>,
+) -> Result<Reservation, QuotaExceeded> {
+    if !enabled() {
+        return Ok(Reservation(None));
+    }
+
+    let mut ledger = LEDGER.lock().unwrap();
+    let reserved = check_in(&mut ledger, path, user, grow, moved_from)?;
+
+    Ok(Reservation(Some(reserved)))
+}
+
+fn check_in(
+    ledger: &mut Ledger,
+    path: &Path,
+    user: Option<&str>,
+    grow: Usage,
+    moved_from: Option<&Path>,
+) -> Result<Reserved, QuotaExceeded> {
+    let (bytes, files) = (grow.bytes, grow.files);
+    let mut reserved = Reserved {
+        dirs: Vec::new(),
+        user: None,
+        grow,
+    };
+
+    for quota in &CONFIG.quotas.dirs {
+        let root = dir_root(&quota.path);
+        if !path.starts_with(&root)
+            || moved_from.map_or(false, |from| from.starts_with(&root))
+        {
+            continue;
+        }
+
+        let (mut usage, serial) = ledger.dir_usage(&root);
+        usage.grow(ledger.pending_dirs.get(&root).cloned().unwrap_or_default());
+        if let Some((unit, max)) =
+            usage.fits(bytes, files, quota.max_bytes, quota.max_files)
+        {
+            return Err(QuotaExceeded {
+                scope: format!("directory '{}'", quota.path.display()),
+                unit,
+                max,
+            });
+        }
+
+        reserved.dirs.push((root, serial));
+    }
+
+    let owner = ledger
+        .owners
+        .get(path)
+        .map(|owned| owned.user.clone())
+        .or_else(|| user.map(String::from))
+        .filter(|_| moved_from.is_none());
+
+    if let Some((owner, quota)) =
+        owner.and_then(|owner| Some((owner.clone(), user_quota(&owner)?)))
+    {
+        let mut usage = ledger.users.get(&owner).cloned().unwrap_or_default();
+        usage.grow(
+            ledger
+                .pending_users
+                .get(&owner)
+                .cloned()
+                .unwrap_or_default(),
+        );
+        if let Some((unit, max)) =
+            usage.fits(bytes, files, quota.max_bytes, quota.max_files)
+        {
+            return Err(QuotaExceeded {
+                scope: format!("user '{}'", owner),
+                unit,
+                max,
+            });
+        }
+
+        reserved.user = Some(owner);
+    }
+
+    for (root, _) in reserved.dirs.iter() {
+        ledger
+            .pending_dirs
+            .entry(root.clone())
+            .or_default()
+            .grow(grow);
+    }
+    if let Some(ref owner) = reserved.user {
+        ledger
+            .pending_users
+            .entry(owner.clone())
+            .or_default()
+            .grow(grow);
+    }
+
+    Ok(reserved)
+}
+
+///Records a write to, or the removal of, the file at the path, which
+///was `before` bytes beforehand.
+pub fn record(path: &Path, user: Option<&str>, before: Option<u64>) {
+    if !enabled() {
+        return;
+    }
+
+    record_in(&mut LEDGER.lock().unwrap(), path, user, before);
+}
+
+fn record_in(
+    ledger: &mut Ledger,
+    path: &Path,
+    user: Option<&str>,
+    before: Option<u64>,
+) {
+    let after = size_of(path);
+
+    for quota in &CONFIG.quotas.dirs {
+        let root = dir_root(&quota.path);
+        if path.starts_with(&root) {
+            if let Some((usage, _)) = ledger.dirs.get_mut(&root) {
+                usage.apply(before, after);
+            }
+        }
+    }
+
+    if CONFIG.quotas.users.is_empty() {
+        return;
+    }
+
+    let owner = match ledger.owners.get(path) {
+        Some(owned) => owned.user.clone(),
+        None => match user {
+            Some(user) => user.into(),
+            None => return,
+        },
+    };
+
+    let tracked = ledger.owners.get(path).map(|owned| owned.size);
+    ledger
+        .users
+        .entry(owner.clone())
+        .or_default()
+        .apply(tracked, after);
+
+    match after {
+        Some(size) => {
+            ledger
+                .owners
+                .insert(path.into(), Owned { user: owner, size });
+        }
+        None => {
+            ledger.owners.remove(path);
+        }
+    }
+
+    ledger.save();
+}
+
+///Forgets every file beneath a directory that was removed as a whole.
+pub fn forget_tree(path: &Path) {
+    if !enabled() {
+        return;
+    }
+
+    let mut ledger = LEDGER.lock().unwrap();
+    ledger.rescan(path);
+
+    let gone: Vec<_> = ledger
+        .owners
+        .keys()
+        .filter(|owned| owned.starts_with(path))
+        .cloned()
+        .collect();
+    if gone.is_empty() {
+        return;
+    }
+
+    for owned in gone {
+        if let Some(owned) = ledger.owners.remove(&owned) {
+            if let Some(usage) = ledger.users.get_mut(&owned.user) {
+                usage.apply(Some(owned.size), None);
+            }
+        }
+    }
+
+    ledger.save();
+}
+
+///Notes that files were moved into the destination outside of the
+///tracked writes, keeping their owners.
+pub fn moved(from: &Path, to: &Path) {
+    if !enabled() {
+        return;
+    }
+
+    moved_in(&mut LEDGER.lock().unwrap(), Some(from), to, None);
+}
+
+///Notes that files were copied or moved into the destination outside
+///of the tracked writes.
+fn moved_in(
+    ledger: &mut Ledger,
+    from: Option<&Path>,
+    to: &Path,
+    user: Option<&str>,
+) {
+    ledger.rescan(to);
+
+    let from = match from {
+        Some(from) => from,
+        None => {
+            if let Some(user) = user.filter(|_| !CONFIG.quotas.users.is_empty())
+            {
+                copied_in(ledger, to, user);
+            }
+            return;
+        }
+    };
+    ledger.rescan(from);
+
+    let moved: Vec<_> = ledger
+        .owners
+        .keys()
+        .filter(|owned| owned.starts_with(from))
+        .cloned()
+        .collect();
+    if moved.is_empty() {
+        return;
+    }
+
+    for path in moved {
+        if let Some(owned) = ledger.owners.remove(&path) {
+            //A moved file is its own prefix, and joining nothing onto the
+            //destination would give it a trailing slash.
+            let dest = match path.strip_prefix(from) {
+                Ok(rel) if rel.as_os_str().is_empty() => to.into(),
+                Ok(rel) => to.join(rel),
+                Err(_) => path.clone(),
+            };
+            ledger.owners.insert(dest, owned);
+        }
+    }
+
+    ledger.save();
+}
+
+///Makes the user the owner of every file of a fresh copy.
+fn copied_in(ledger: &mut Ledger, to: &Path, user: &str) {
+    let mut copies = Vec::new();
+    match std::fs::symlink_metadata(to) {
+        Ok(meta) if meta.is_dir() => {
+            scan(to, &mut |path, len| copies.push((path.to_path_buf(), len)))
+        }
+        Ok(meta) => copies.push((to.into(), meta.len())),
+        Err(_) => return,
+    }
+
+    for (path, size) in copies {
+        ledger
+            .users
+            .entry(user.into())
+            .or_default()
+            .apply(None, Some(size));
+        ledger.owners.insert(
+            path,
+            Owned {
+                user: user.into(),
+                size,
+            },
+        );
+    }
+
+    ledger.save();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let mut usage = Usage::default();
+        usage.apply(None, Some(100));
+        usage.apply(None, Some(50));
+        usage.apply(Some(100), Some(20));
+        assert_eq!(
+            usage,
+            Usage {
+                bytes: 70,
+                files: 2,
+            }
+        );
+
+        usage.apply(Some(50), None);
+        assert_eq!(
+            usage,
+            Usage {
+                bytes: 20,
+                files: 1,
+            }
+        );
+
+        assert_eq!(usage.fits(80, 1, 100, 2), None);
+        assert_eq!(usage.fits(81, 0, 100, 2), Some(("bytes", 100)));
+        assert_eq!(usage.fits(0, 1, 100, 1), Some(("files", 1)));
+        assert_eq!(usage.fits(1 << 40, 1, 0, 0), None);
+
+        //Shrinking a file never trips a quota that's already full.
+        assert_eq!(usage.fits(0, 0, 10, 1), None);
+
+        let reserved = Usage { bytes: 30, files: 1 };
+        usage.grow(reserved);
+        assert_eq!(usage.fits(51, 0, 100, 0), Some(("bytes", 100)));
+        usage.shrink(reserved);
+        usage.shrink(reserved);
+        assert_eq!(usage, Usage::default());
+    }
+
+    #[test]
+    fn test_usage_of() {
+        let dir =
+            std::env::temp_dir().join(format!("quota-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "12345").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "123").unwrap();
+
+        assert_eq!(usage_of(&dir), Usage { bytes: 8, files: 2 });
+        assert_eq!(usage_of(&dir.join("a.txt")), Usage { bytes: 5, files: 1 });
+        assert_eq!(usage_of(&dir.join("missing")), Usage::default());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+use super::auth_handler::AuthHandler;
+use super::quota;
+use super::versions;
+use crate::webserver::requests::Request;
+use crate::CONFIG;
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum RemovalError {
+    IoError(std::io::Error),
+    NotFound(PathBuf),
+    Protected(PathBuf),
+    RecursionDisabled(PathBuf),
+    TooDeep(usize),
+}
+
+impl Display for RemovalError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        use RemovalError::*;
+
+        match self {
+            IoError(err) => write!(fmt, "{}", err),
+            NotFound(path) => {
+                write!(fmt, "'{}' does not exist", path.display())
+            }
+            Protected(path) => {
+                write!(fmt, "'{}' is protected", path.display())
+            }
+            RecursionDisabled(path) => write!(
+                fmt,
+                "'{}' is a directory and recursive deletes are disabled",
+                path.display()
+            ),
+            TooDeep(max) => write!(
+                fmt,
+                "directory is nested deeper than the limit of {}",
+                max
+            ),
+        }
+    }
+}
+
+impl Error for RemovalError {}
+
+impl From<std::io::Error> for RemovalError {
+    fn from(err: std::io::Error) -> Self { RemovalError::IoError(err) }
+}
+
+type Result<T> = std::result::Result<T, RemovalError>;
+
+///Whether a path may never be removed, either because it is an auth
+///file or because it belongs to the history store.
+pub fn is_protected(path: &Path) -> bool {
+    AuthHandler::is_auth_file(path) || versions::is_store_path(path)
+}
+
+///Lists every file beneath a directory, refusing when anything in it
+///is protected or it nests deeper than `max_depth` levels.
+pub fn collect(dir: &Path, max_depth: usize) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_into(dir, max_depth, &mut files)?;
+
+    Ok(files)
+}
+
+fn collect_into(
+    dir: &Path,
+    depth_left: usize,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_protected(&path) {
+            return Err(RemovalError::Protected(path));
+        }
+
+        //Symlinks are removed themselves, never followed.
+        let meta = std::fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            if depth_left == 0 {
+                return Err(RemovalError::TooDeep(CONFIG.deletes.max_depth));
+            }
+            collect_into(&path, depth_left - 1, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+///Removes a file, or a whole directory when the config allows it. A
+///version of every file is kept first, and nothing is removed unless
+///the entire tree can be.
+pub fn remove(path: &Path, req: &Request, user: Option<&str>) -> Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(RemovalError::NotFound(path.into()));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if is_protected(path) {
+        return Err(RemovalError::Protected(path.into()));
+    }
+
+    if !meta.is_dir() {
+        versions::keep(path, req, user)?;
+        std::fs::remove_file(path)?;
+        quota::record(path, None, Some(meta.len()));
+        return Ok(());
+    }
+
+    if !CONFIG.deletes.recursive {
+        return Err(RemovalError::RecursionDisabled(path.into()));
+    }
+
+    let files = collect(path, CONFIG.deletes.max_depth)?;
+    for file in &files {
+        versions::keep(file, req, user)?;
+    }
+
+    log::debug!(
+        "removing '{}' with {} files beneath it",
+        path.display(),
+        files.len()
+    );
+    std::fs::remove_dir_all(path)?;
+    quota::forget_tree(path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let dir = std::env::temp_dir()
+            .join(format!("removal-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("top.txt"), "top").unwrap();
+        std::fs::write(dir.join("a/b/deep.txt"), "deep").unwrap();
+
+        let mut files = collect(&dir, 2).unwrap();
+        files.sort();
+        assert_eq!(files, vec![dir.join("a/b/deep.txt"), dir.join("top.txt")]);
+
+        match collect(&dir, 1) {
+            Err(RemovalError::TooDeep(_)) => (),
+            res => panic!("expected the depth limit to apply: {:?}", res),
+        }
+
+        let auth = dir.join("a").join(&CONFIG.auth.file_name);
+        std::fs::write(&auth, "authorization-type=Basic").unwrap();
+        match collect(&dir, 2) {
+            Err(RemovalError::Protected(path)) => assert_eq!(path, auth),
+            res => panic!("expected the auth file to be protected: {:?}", res),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
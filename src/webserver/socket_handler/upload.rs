@@ -0,0 +1,187 @@
+use crate::webserver::shared::headers::*;
+use crate::CONFIG;
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum UploadError {
+    IoError(std::io::Error),
+    MissingParent(PathBuf),
+    IsDirectory(PathBuf),
+    RangeMismatch { expected: u64, got: u64 },
+    RangeNotSatisfiable(u64),
+}
+
+impl Display for UploadError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        use UploadError::*;
+
+        match self {
+            IoError(err) => write!(fmt, "{}", err),
+            MissingParent(path) => write!(
+                fmt,
+                "parent directory '{}' does not exist",
+                path.display()
+            ),
+            IsDirectory(path) => {
+                write!(fmt, "'{}' is a directory", path.display())
+            }
+            RangeMismatch { expected, got } => write!(
+                fmt,
+                "content range covers {} bytes but {} were sent",
+                expected, got
+            ),
+            RangeNotSatisfiable(len) => write!(
+                fmt,
+                "content range starts past the end of the file ({} bytes)",
+                len
+            ),
+        }
+    }
+}
+
+impl Error for UploadError {}
+
+impl From<std::io::Error> for UploadError {
+    fn from(err: std::io::Error) -> Self { UploadError::IoError(err) }
+}
+
+type Result<T> = std::result::Result<T, UploadError>;
+
+///Produces a hidden name next to the target for the upload to be
+///staged in, so the rename into place never crosses filesystems.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|dur| dur.subsec_nanos())
+        .unwrap_or(0);
+
+    path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        nanos
+    ))
+}
+
+///Makes sure the directory the upload is going into exists, creating
+///it when the config allows.
+fn prepare_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return Ok(()),
+    };
+
+    if parent.is_dir() {
+        Ok(())
+    } else if CONFIG.uploads.create_dirs {
+        log::debug!("creating directories for '{}'", path.display());
+        Ok(std::fs::create_dir_all(parent)?)
+    } else {
+        Err(UploadError::MissingParent(parent.into()))
+    }
+}
+
+///Stages the new contents of the file in a temporary file.
+fn stage(
+    path: &Path,
+    temp: &Path,
+    payload: &[u8],
+    range: Option<&ContentRange>,
+) -> Result<()> {
+    let mut file = match range {
+        Some(range) => {
+            let expected = range.span.len();
+            if expected != payload.len() as u64 {
+                return Err(UploadError::RangeMismatch {
+                    expected,
+                    got: payload.len() as u64,
+                });
+            }
+
+            let len = if path.exists() {
+                std::fs::copy(path, temp)?
+            } else {
+                File::create(temp)?;
+                0
+            };
+
+            if range.span.start > len {
+                return Err(UploadError::RangeNotSatisfiable(len));
+            }
+
+            let mut file = OpenOptions::new().write(true).open(temp)?;
+            file.seek(SeekFrom::Start(range.span.start))?;
+            file
+        }
+        None => File::create(temp)?,
+    };
+
+    file.write_all(payload)?;
+    file.sync_all()?;
+
+    //The temp file replaces the target, so it takes on its permissions,
+    //or an edited CGI script would stop being executable.
+    if let Ok(meta) = path.metadata() {
+        std::fs::set_permissions(temp, meta.permissions())?;
+    }
+
+    Ok(())
+}
+
+///Writes the payload of a PUT to the given path. The new contents are
+///written and synced to a temporary file which is then renamed over
+///the target, so a failed upload never leaves a truncated file behind.
+///With a content range, only that part of the existing file changes.
+pub fn write_atomic(
+    path: &Path,
+    payload: &[u8],
+    range: Option<&ContentRange>,
+) -> Result<()> {
+    if path.is_dir() {
+        return Err(UploadError::IsDirectory(path.into()));
+    }
+
+    prepare_parent(path)?;
+
+    let temp = temp_path(path);
+    let res = stage(path, &temp, payload, range)
+        .and_then(|_| Ok(std::fs::rename(&temp, path)?));
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    } else if let Some(parent) = path.parent() {
+        //Sync the directory as well so the rename itself is durable.
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    res
+}
+
+///Appends the payload of a PATCH to the end of the file, creating it
+///when it doesn't exist yet. Unlike a PUT this writes in place, so
+///log-style files can keep growing while they are being read.
+pub fn append(path: &Path, payload: &[u8]) -> Result<()> {
+    if path.is_dir() {
+        return Err(UploadError::IsDirectory(path.into()));
+    }
+
+    prepare_parent(path)?;
+
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    file.write_all(payload)?;
+    file.sync_data()?;
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use crate::webserver::requests::Request;
+use crate::CONFIG;
+
+use chrono::{DateTime, Duration, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::io::Result as ioResult;
+use std::path::{Path, PathBuf};
+
+///A single kept copy of a file from before it was overwritten or
+///deleted. The content lives next to its metadata in the store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Version {
+    pub id:     String,
+    pub time:   DateTime<Utc>,
+    pub user:   Option<String>,
+    pub size:   u64,
+    pub method: String,
+    ///When the kept content was last written, which is when it became
+    ///the current state of the file.
+    #[serde(default)]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+///The root of the history store.
+pub fn store_root() -> PathBuf {
+    if CONFIG.versioning.store.is_absolute() {
+        CONFIG.versioning.store.clone()
+    } else {
+        CONFIG.root.join(&CONFIG.versioning.store)
+    }
+}
+
+///Whether the path points inside the history store, which is only
+///ever reached through the version query parameters.
+pub fn is_store_path(path: &Path) -> bool {
+    CONFIG.versioning.enabled && path.starts_with(store_root())
+}
+
+///The directory holding every version of the given file.
+fn history_dir(path: &Path) -> PathBuf {
+    let rel = path.strip_prefix(&CONFIG.root).unwrap_or(path);
+
+    store_root().join(rel)
+}
+
+fn meta_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+///Keeps a copy of the file as it is right now, before a request
+///replaces or removes it, on behalf of the user whose credentials an
+///auth file checked. Nothing is kept if versioning is disabled or the
+///file doesn't exist yet.
+pub fn keep(
+    path: &Path,
+    req: &Request,
+    user: Option<&str>,
+) -> ioResult<Option<Version>> {
+    if !CONFIG.versioning.enabled || !path.is_file() {
+        return Ok(None);
+    }
+
+    let dir = history_dir(path);
+    std::fs::create_dir_all(&dir)?;
+
+    //Ids are timestamps so that sorting them sorts the versions.
+    let time = Utc::now();
+    let id = time.format("%Y%m%d%H%M%S%9f").to_string();
+
+    let modified = path.metadata()?.modified()?.into();
+
+    let version = Version {
+        size: std::fs::copy(path, dir.join(&id))?,
+        id,
+        time,
+        user: user.map(String::from),
+        method: req.method.to_string(),
+        modified: Some(modified),
+    };
+
+    let meta = serde_json::to_vec_pretty(&version)?;
+    std::fs::write(meta_path(&dir, &version.id), meta)?;
+
+    debug!(
+        "kept version '{}' of '{}' before {}",
+        version.id,
+        path.display(),
+        version.method
+    );
+
+    if let Err(err) = prune(path) {
+        warn!("failed to prune versions of '{}': '{}'", path.display(), err);
+    }
+
+    Ok(Some(version))
+}
+
+///Lists the kept versions of a file, oldest first.
+pub fn list(path: &Path) -> ioResult<Vec<Version>> {
+    let dir = history_dir(path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?.path();
+        if entry.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+
+        let meta = std::fs::read(&entry)?;
+        match serde_json::from_slice::<Version>(&meta) {
+            Ok(version) => ret.push(version),
+            Err(err) => {
+                warn!("skipping bad version '{}': '{}'", entry.display(), err)
+            }
+        }
+    }
+
+    ret.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(ret)
+}
+
+///Where the content of the given version is kept.
+pub fn content_path(path: &Path, version: &Version) -> PathBuf {
+    history_dir(path).join(&version.id)
+}
+
+///Finds a version of the file by id, along with the location of its
+///kept content.
+pub fn find(path: &Path, id: &str) -> Option<(Version, PathBuf)> {
+    let version = list(path).ok()?.into_iter().find(|ver| ver.id == id)?;
+    let content = content_path(path, &version);
+
+    if content.is_file() {
+        Some((version, content))
+    } else {
+        None
+    }
+}
+
+fn remove(dir: &Path, version: &Version) -> ioResult<()> {
+    std::fs::remove_file(dir.join(&version.id))?;
+    std::fs::remove_file(meta_path(dir, &version.id))
+}
+
+///Applies the retention policy, dropping the oldest versions past the
+///configured count and any older than the configured age.
+fn prune(path: &Path) -> ioResult<()> {
+    let dir = history_dir(path);
+    let mut versions = list(path)?;
+
+    if CONFIG.versioning.max_age_days > 0 {
+        let cutoff =
+            Utc::now() - Duration::days(CONFIG.versioning.max_age_days as i64);
+
+        for version in versions.iter().filter(|ver| ver.time < cutoff) {
+            remove(&dir, version)?;
+        }
+        versions.retain(|ver| ver.time >= cutoff);
+    }
+
+    if CONFIG.versioning.max_versions > 0
+        && versions.len() > CONFIG.versioning.max_versions
+    {
+        let excess = versions.len() - CONFIG.versioning.max_versions;
+        for version in versions.iter().take(excess) {
+            remove(&dir, version)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,794 @@
+pub mod locks;
+pub mod props;
+mod xml;
+
+use locks::{Lock, Scope};
+use props::PropName;
+use xml::*;
+
+use super::quota;
+use super::removal::{self, is_protected, RemovalError};
+use super::{AuthCheckResult, AuthHandler, SocketHandler};
+use crate::webserver::requests::Request;
+use crate::webserver::responses::*;
+use crate::webserver::shared::*;
+use crate::CONFIG;
+
+use log::*;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Depth {
+    Zero,
+    One,
+    Infinity,
+}
+
+impl Depth {
+    /// Reads the Depth header, falling back to the default for the
+    /// method when it is missing.
+    fn of(headers: &HeaderList, default: Depth) -> Result<Self, Response> {
+        match headers.get(DEPTH).map(|val| val.trim().to_lowercase()) {
+            None => Ok(default),
+            Some(ref val) if val == "0" => Ok(Depth::Zero),
+            Some(ref val) if val == "1" => Ok(Depth::One),
+            Some(ref val) if val == "infinity" => Ok(Depth::Infinity),
+            Some(_) => Err(Response::bad_request()),
+        }
+    }
+}
+
+/// Percent encodes the segments of a request path for use in an href.
+fn encode_href(path: &str) -> String {
+    path.split('/')
+        .map(urlencoding::encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The href of a resource, with collections ending in a slash.
+fn href(path: &str, is_dir: bool) -> String {
+    let mut ret = encode_href(path);
+    if is_dir && !ret.ends_with('/') {
+        ret.push('/');
+    }
+
+    ret
+}
+
+fn payload_response(code: StatusCode) -> Response {
+    let mut headers = HeaderList::response_headers();
+    headers.content_length(0);
+
+    Response {
+        code,
+        headers,
+        data: None,
+    }
+}
+
+/// Checks the locks a request would be blocked by, unless it submitted
+/// their tokens in the If header.
+pub fn check_locks(req: &Request, url: &Path) -> Option<Response> {
+    let tokens = locks::submitted_tokens(&req.headers);
+
+    let mut targets: Vec<(PathBuf, bool)> = match req.method {
+        Method::Put | Method::Patch | Method::Proppatch | Method::Mkcol => {
+            vec![(url.into(), false)]
+        }
+        Method::Delete | Method::Move => vec![(url.into(), true)],
+        _ => vec![],
+    };
+
+    if req.method == Method::Copy || req.method == Method::Move {
+        if let Ok(Some(dest)) = destination(req).map(|dest| dest.ok()) {
+            targets.push((dest, true));
+        }
+    }
+
+    for (path, deep) in targets {
+        if locks::blocked(&path, deep, &tokens) {
+            debug!("'{}' is locked", path.display());
+            return Some(payload_response(StatusCode::Locked));
+        }
+    }
+
+    None
+}
+
+enum PropRequest {
+    AllProp,
+    PropName,
+    Props(Vec<PropName>),
+}
+
+fn parse_propfind(req: &Request) -> Result<PropRequest, ()> {
+    let elem = match parse_body(req.payload.as_ref())? {
+        Some(elem) => elem,
+        None => return Ok(PropRequest::AllProp),
+    };
+
+    if !is_dav(&elem, "propfind") {
+        return Err(());
+    }
+
+    for child in children(&elem) {
+        if is_dav(child, "allprop") {
+            return Ok(PropRequest::AllProp);
+        } else if is_dav(child, "propname") {
+            return Ok(PropRequest::PropName);
+        } else if is_dav(child, "prop") {
+            return Ok(PropRequest::Props(
+                children(child).map(prop_name).collect(),
+            ));
+        }
+    }
+
+    Err(())
+}
+
+/// Whether the path is a directory itself, rather than a file or a
+/// symlink to either.
+fn is_real_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).map_or(false, |meta| meta.is_dir())
+}
+
+/// Whether the credentials of the request pass the auth file that
+/// applies to a directory, which can differ from the one it was made to.
+fn passes_auth(req: &Request, dir: &Path) -> bool {
+    matches!(
+        AuthHandler::new(dir).map(|auth| auth.check(req)),
+        Ok(Ok(AuthCheckResult::Passed))
+    )
+}
+
+/// Collects the resources a request at the given depth reaches, with
+/// their request paths. Directories the request can't pass the auth
+/// file of are left out, and symlinks are never followed.
+fn walk(
+    req: &Request,
+    path: &Path,
+    req_path: &str,
+    depth: Depth,
+    out: &mut Vec<(PathBuf, String)>,
+) {
+    out.push((path.into(), req_path.into()));
+
+    if depth == Depth::Zero || !is_real_dir(path) {
+        return;
+    }
+
+    let mut entries: Vec<_> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(err) => {
+            warn!("failed to read '{}': '{}'", path.display(), err);
+            return;
+        }
+    };
+    entries.sort();
+
+    let next = if depth == Depth::One {
+        Depth::Zero
+    } else {
+        Depth::Infinity
+    };
+
+    for entry in entries.iter().filter(|entry| !is_protected(entry)) {
+        if is_real_dir(entry) && !passes_auth(req, entry) {
+            debug!("leaving '{}' out of the listing", entry.display());
+            continue;
+        }
+
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        let child = format!("{}/{}", req_path.trim_end_matches('/'), name);
+
+        walk(req, entry, &child, next, out);
+    }
+}
+
+pub fn propfind(req: &Request, url: &Path) -> Response {
+    if !url.exists() || is_protected(url) {
+        return Response::not_found();
+    }
+
+    //Listing a whole tree in one response is refused, as RFC 4918
+    //allows, so clients have to walk it a level at a time.
+    let depth = match Depth::of(&req.headers, Depth::Infinity) {
+        Ok(Depth::Infinity) => {
+            return xml_response(
+                StatusCode::Forbidden,
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error \
+                 xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"
+                    .into(),
+            );
+        }
+        Ok(depth) => depth,
+        Err(resp) => return resp,
+    };
+
+    let request = match parse_propfind(req) {
+        Ok(request) => request,
+        Err(()) => return Response::bad_request(),
+    };
+
+    let mut resources = Vec::new();
+    walk(req, url, &req.path.to_string_lossy(), depth, &mut resources);
+
+    let mut multi = Multistatus::new();
+    for (path, req_path) in resources {
+        let stats = match request {
+            PropRequest::AllProp => vec![(
+                StatusCode::Ok,
+                props::all(&path)
+                    .iter()
+                    .map(|(name, val)| prop(name, Some(val)))
+                    .collect(),
+            )],
+            PropRequest::PropName => vec![(
+                StatusCode::Ok,
+                props::all(&path)
+                    .iter()
+                    .map(|(name, _)| prop(name, None))
+                    .collect(),
+            )],
+            PropRequest::Props(ref names) => {
+                let mut found = Vec::new();
+                let mut missing = Vec::new();
+
+                for name in names {
+                    match props::get(&path, name) {
+                        Some(val) => found.push(prop(name, Some(&val))),
+                        None => missing.push(prop(name, None)),
+                    }
+                }
+
+                vec![(StatusCode::Ok, found), (StatusCode::NotFound, missing)]
+            }
+        };
+
+        multi.propstat(&href(&req_path, path.is_dir()), &stats);
+    }
+
+    multi.into_response()
+}
+
+enum PropUpdate {
+    Set(PropName, String),
+    Remove(PropName),
+}
+
+fn parse_proppatch(req: &Request) -> Result<Vec<PropUpdate>, ()> {
+    let elem = parse_body(req.payload.as_ref())?.ok_or(())?;
+    if !is_dav(&elem, "propertyupdate") {
+        return Err(());
+    }
+
+    let mut updates = Vec::new();
+    for action in children(&elem) {
+        let set = is_dav(action, "set");
+        if !set && !is_dav(action, "remove") {
+            return Err(());
+        }
+
+        for container in children(action).filter(|child| is_dav(child, "prop"))
+        {
+            for prop in children(container) {
+                let name = prop_name(prop);
+                updates.push(if set {
+                    let val = prop.get_text().map(|val| val.into_owned());
+                    PropUpdate::Set(name, val.unwrap_or_default())
+                } else {
+                    PropUpdate::Remove(name)
+                });
+            }
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Sets and removes dead properties. The instructions are applied all
+/// together or not at all, so one protected property fails the rest.
+pub fn proppatch(req: &Request, url: &Path) -> Response {
+    if is_protected(url) {
+        return Response::forbidden();
+    } else if !url.exists() {
+        return Response::not_found();
+    }
+
+    let updates = match parse_proppatch(req) {
+        Ok(updates) => updates,
+        Err(()) => return Response::bad_request(),
+    };
+
+    let name_of = |update: &PropUpdate| match update {
+        PropUpdate::Set(name, _) | PropUpdate::Remove(name) => name.clone(),
+    };
+
+    let failed = updates
+        .iter()
+        .any(|update| props::is_protected(&name_of(update)));
+
+    let mut ok = Vec::new();
+    let mut forbidden = Vec::new();
+    let mut dependent = Vec::new();
+
+    for update in updates {
+        let name = name_of(&update);
+        let rendered = prop(&name, None);
+
+        if props::is_protected(&name) {
+            forbidden.push(rendered);
+        } else if failed {
+            dependent.push(rendered);
+        } else {
+            match update {
+                PropUpdate::Set(name, val) => props::set(url, name, val),
+                PropUpdate::Remove(name) => props::remove(url, &name),
+            }
+            ok.push(rendered);
+        }
+    }
+
+    let mut multi = Multistatus::new();
+    multi.propstat(
+        &href(&req.path.to_string_lossy(), url.is_dir()),
+        &[
+            (StatusCode::Ok, ok),
+            (StatusCode::Forbidden, forbidden),
+            (StatusCode::FailedDependency, dependent),
+        ],
+    );
+
+    multi.into_response()
+}
+
+pub fn mkcol(req: &Request, url: &Path) -> Response {
+    if is_protected(url) {
+        return Response::forbidden();
+    }
+
+    if req
+        .payload
+        .as_ref()
+        .map_or(false, |payload| !payload.is_empty())
+    {
+        return payload_response(StatusCode::UnsupportedMediaType);
+    }
+
+    if url.exists() {
+        let mut allows = AuthHandler::new(url)
+            .map(|auth| auth.allows())
+            .unwrap_or_default();
+        allows.retain(|method| *method != Method::Mkcol);
+
+        return Response::not_allowed(allows);
+    }
+
+    match std::fs::create_dir(url) {
+        Ok(()) => payload_response(StatusCode::Created),
+        Err(err) => {
+            use std::io::ErrorKind;
+
+            match err.kind() {
+                ErrorKind::NotFound => Response::conflict(
+                    "The collection's parent does not exist.",
+                ),
+                ErrorKind::PermissionDenied => Response::forbidden(),
+                _ => {
+                    error!("failed to create '{}': '{}'", url.display(), err);
+                    Response::internal_error()
+                }
+            }
+        }
+    }
+}
+
+/// Resolves the Destination header of a COPY or MOVE to a path under
+/// the root. Destinations on other servers give a 502.
+fn destination(req: &Request) -> Result<Result<PathBuf, Response>, Response> {
+    let dest = req
+        .headers
+        .get(DESTINATION)
+        .ok_or_else(Response::bad_request)?
+        .trim();
+
+    let path = if dest.starts_with('/') {
+        dest.split('?').next().unwrap_or_default().to_string()
+    } else {
+        let url = url::Url::parse(dest).map_err(|_| Response::bad_request())?;
+        let host = url.host_str().unwrap_or_default();
+        let ours = req
+            .headers
+            .get(HOST)
+            .map(|ours| ours.split(':').next().unwrap_or_default());
+
+        if ours.map_or(false, |ours| !ours.eq_ignore_ascii_case(host)) {
+            return Ok(Err(payload_response(StatusCode::BadGateway)));
+        }
+
+        url.path().to_string()
+    };
+
+    let path = PathBuf::from(
+        urlencoding::decode(&path).map_err(|_| Response::bad_request())?,
+    );
+
+    //The root check below compares components without resolving them,
+    //so dot segments have to be refused before they reach it.
+    let dotted = path
+        .components()
+        .any(|comp| !matches!(comp, Component::RootDir | Component::Normal(_)));
+    if dotted {
+        return Ok(Err(Response::forbidden()));
+    }
+
+    let url = SocketHandler::sterilize_path(&path);
+
+    if url.starts_with(&CONFIG.root) {
+        Ok(Ok(url))
+    } else {
+        Ok(Err(Response::forbidden()))
+    }
+}
+
+fn remove_any(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_link(from: &Path, _: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        format!("can't copy the symlink '{}'", from.display()),
+    ))
+}
+
+/// Copies a file or a collection, refusing anything protected so an
+/// auth file never gets left behind by its subtree. Symlinks are copied
+/// as links, never followed.
+fn copy_tree(from: &Path, to: &Path, deep: bool) -> Result<(), RemovalError> {
+    if is_protected(from) {
+        return Err(RemovalError::Protected(from.into()));
+    }
+
+    let meta = std::fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        return Ok(copy_link(from, to)?);
+    } else if !meta.is_dir() {
+        std::fs::copy(from, to)?;
+        return Ok(());
+    }
+
+    std::fs::create_dir(to)?;
+    if deep {
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?.path();
+            copy_tree(&entry, &to.join(entry.file_name().unwrap()), deep)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves a collection by copying it and removing the source the way a
+/// DELETE would, keeping versions of its files.
+fn copy_and_remove(
+    req: &Request,
+    from: &Path,
+    to: &Path,
+    user: Option<&str>,
+    reservation: quota::Reservation,
+) -> Result<(), RemovalError> {
+    if is_real_dir(from) {
+        if !CONFIG.deletes.recursive {
+            return Err(RemovalError::RecursionDisabled(from.into()));
+        }
+        removal::collect(from, CONFIG.deletes.max_depth)?;
+    }
+
+    copy_tree(from, to, true)?;
+    reservation.moved(Some(from), to, None);
+
+    let res = removal::remove(from, req, user);
+    if res.is_err() {
+        //The source stays, so it gets its owners back and loses the copy.
+        quota::moved(to, from);
+        if let Err(err) = remove_any(to) {
+            error!("failed to undo copy to '{}': '{}'", to.display(), err);
+        }
+    }
+
+    res
+}
+
+/// Handles both COPY and MOVE, which share their checks on the
+/// destination and the Overwrite header.
+pub fn copy_move(req: &Request, url: &Path, user: Option<&str>) -> Response {
+    let moving = req.method == Method::Move;
+
+    if is_protected(url) {
+        return Response::forbidden();
+    } else if !url.exists() {
+        return Response::not_found();
+    }
+
+    let dest = match destination(req) {
+        Ok(Ok(dest)) => dest,
+        Ok(Err(resp)) | Err(resp) => return resp,
+    };
+
+    if is_protected(&dest)
+        || dest == url
+        || dest.starts_with(url) && url.is_dir()
+    {
+        return Response::forbidden();
+    }
+
+    //The destination must be writable with the same credentials.
+    match AuthHandler::new(&dest).map(|auth| auth.check(req)) {
+        Ok(Ok(AuthCheckResult::Passed)) => (),
+        _ => return Response::forbidden(),
+    }
+
+    let depth = match Depth::of(&req.headers, Depth::Infinity) {
+        Ok(Depth::One) | Err(_) => return Response::bad_request(),
+        Ok(Depth::Zero) if moving => return Response::bad_request(),
+        Ok(depth) => depth,
+    };
+
+    //A copy must not publish a subtree without the auth file guarding
+    //it, so trees holding one are refused before anything is touched.
+    if !moving && depth == Depth::Infinity && is_real_dir(url) {
+        if let Err(err) = removal::collect(url, CONFIG.deletes.max_depth) {
+            return SocketHandler::removal_failed(&url.into(), err);
+        }
+    }
+
+    let overwrite = req
+        .headers
+        .get(OVERWRITE)
+        .map_or(true, |val| !val.trim().eq_ignore_ascii_case("f"));
+
+    let existed = dest.exists();
+    if existed {
+        if !overwrite {
+            return Response::precondition_failed();
+        }
+
+        if let Err(err) = removal::remove(&dest, req, user) {
+            return SocketHandler::removal_failed(&dest, err);
+        }
+        props::forget(&dest);
+        locks::forget(&dest);
+    }
+
+    if dest.parent().map_or(true, |parent| !parent.is_dir()) {
+        return Response::conflict("The destination's parent does not exist.");
+    }
+
+    let usage = if depth == Depth::Infinity {
+        quota::usage_of(url)
+    } else {
+        Default::default()
+    };
+    let reservation = match quota::reserve_tree(url, &dest, user, usage, moving)
+    {
+        Ok(reservation) => reservation,
+        Err(err) => {
+            warn!("refusing {} to '{}': '{}'", req.method, dest.display(), err);
+            return Response::insufficient_storage(&err);
+        }
+    };
+
+    let res = if moving {
+        match std::fs::rename(url, &dest) {
+            Ok(()) => {
+                reservation.moved(Some(url), &dest, None);
+                Ok(())
+            }
+            //Renames fail across filesystems, so fall back to copying.
+            Err(_) => copy_and_remove(req, url, &dest, user, reservation),
+        }
+    } else {
+        copy_tree(url, &dest, depth == Depth::Infinity)
+            .map(|()| reservation.moved(None, &dest, user))
+    };
+
+    match res {
+        Ok(()) => {
+            props::copy(url, &dest);
+            if moving {
+                props::forget(url);
+                locks::forget(url);
+            }
+
+            payload_response(if existed {
+                StatusCode::NoContent
+            } else {
+                StatusCode::Created
+            })
+        }
+        Err(RemovalError::IoError(err)) => {
+            error!(
+                "failed to {} '{}' to '{}': '{}'",
+                req.method,
+                url.display(),
+                dest.display(),
+                err
+            );
+            Response::internal_error()
+        }
+        Err(err) => SocketHandler::removal_failed(&url.into(), err),
+    }
+}
+
+fn lock_response(code: StatusCode, lock: &Lock) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop \
+         xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\n",
+        props::active_lock(lock)
+    );
+
+    let mut resp = xml_response(code, body);
+    resp.headers.lock_token(&lock.token);
+    resp
+}
+
+/// Takes out a new lock, or refreshes one when the request has no body.
+/// Locking a missing resource creates it empty.
+pub fn lock(req: &Request, url: &Path, user: Option<&str>) -> Response {
+    if is_protected(url) {
+        return Response::forbidden();
+    }
+
+    let timeout = locks::timeout(&req.headers);
+
+    let info = match parse_body(req.payload.as_ref()) {
+        Ok(Some(info)) if is_dav(&info, "lockinfo") => info,
+        Ok(None) => {
+            let tokens = locks::submitted_tokens(&req.headers);
+            return match locks::refresh(url, &tokens, timeout) {
+                Some(lock) => lock_response(StatusCode::Ok, &lock),
+                None => Response::precondition_failed(),
+            };
+        }
+        _ => return Response::bad_request(),
+    };
+
+    let deep = match Depth::of(&req.headers, Depth::Infinity) {
+        Ok(Depth::Zero) => false,
+        Ok(Depth::Infinity) => true,
+        _ => return Response::bad_request(),
+    };
+
+    let scope = children(&info)
+        .filter(|child| is_dav(child, "lockscope"))
+        .flat_map(children)
+        .find_map(|scope| {
+            if is_dav(scope, "exclusive") {
+                Some(Scope::Exclusive)
+            } else if is_dav(scope, "shared") {
+                Some(Scope::Shared)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(Scope::Exclusive);
+
+    let owner =
+        children(&info)
+            .find(|child| is_dav(child, "owner"))
+            .map(|owner| {
+                match children(owner).find(|child| is_dav(child, "href")) {
+                    Some(href) => format!(
+                        "<D:href>{}</D:href>",
+                        escape(&href.get_text().unwrap_or_default())
+                    ),
+                    None => escape(&owner.get_text().unwrap_or_default()),
+                }
+            });
+
+    let lock = Lock::new(
+        url,
+        &req.path.to_string_lossy(),
+        scope,
+        deep,
+        owner,
+        timeout,
+    );
+
+    let lock = match locks::acquire(lock) {
+        Some(lock) => lock,
+        None => return payload_response(StatusCode::Locked),
+    };
+
+    if url.exists() {
+        return lock_response(StatusCode::Ok, &lock);
+    }
+
+    let reservation = match quota::reserve(url, user, 0, true) {
+        Ok(reservation) => reservation,
+        Err(err) => {
+            locks::release(url, &lock.token);
+            warn!("refusing LOCK of '{}': '{}'", url.display(), err);
+            return Response::insufficient_storage(&err);
+        }
+    };
+
+    match std::fs::File::create(url) {
+        Ok(_) => {
+            reservation.record(url, user, None);
+            lock_response(StatusCode::Created, &lock)
+        }
+        Err(err) => {
+            locks::release(url, &lock.token);
+            warn!("failed to create locked '{}': '{}'", url.display(), err);
+            Response::conflict("The resource could not be created.")
+        }
+    }
+}
+
+pub fn unlock(req: &Request, url: &Path) -> Response {
+    let token = match req.headers.get(LOCK_TOKEN) {
+        Some(token) => {
+            token.trim().trim_start_matches('<').trim_end_matches('>')
+        }
+        None => return Response::bad_request(),
+    };
+
+    if locks::release(url, token) {
+        Response::no_content()
+    } else {
+        Response::conflict("The lock token does not apply to this resource.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destination() {
+        let dest = |dest: &str| {
+            let req: Request = format!(
+                "COPY /a.txt HTTP/1.1\r\nHost: localhost\r\nDestination: \
+                 {}\r\n\r\n",
+                dest
+            )
+            .parse()
+            .unwrap();
+            destination(&req).unwrap()
+        };
+
+        assert_eq!(dest("/sub/b.txt").unwrap(), CONFIG.root.join("sub/b.txt"));
+        assert_eq!(
+            dest("http://localhost/sub/b%20c.txt").unwrap(),
+            CONFIG.root.join("sub/b c.txt")
+        );
+        assert_eq!(
+            dest("http://elsewhere/b.txt").unwrap_err().code,
+            StatusCode::BadGateway
+        );
+
+        for bad in &[
+            "/sub/../../../tmp/x",
+            "/sub/%2e%2e/%2e%2e/tmp/x",
+            "http://localhost/sub/..%2f..%2ftmp/x",
+        ] {
+            assert_eq!(dest(bad).unwrap_err().code, StatusCode::Forbidden);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::webserver::shared::*;
+use crate::CONFIG;
+
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref LOCKS: RwLock<Vec<Lock>> = {
+        Default::default()
+    };
+
+    static ref TOKEN: Regex =
+        Regex::new("<(opaquelocktoken:[^>]+)>").expect("lock token regex");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scope {
+    Exclusive,
+    Shared,
+}
+
+/// A write lock held on a resource. Locks only live in memory, so they
+/// are all released when the server restarts.
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub token:   String,
+    pub path:    PathBuf,
+    pub root:    String,
+    pub scope:   Scope,
+    pub deep:    bool,
+    pub owner:   Option<String>,
+    pub timeout: Duration,
+    pub expires: Instant,
+}
+
+impl Lock {
+    pub fn new(
+        path: &Path,
+        root: &str,
+        scope: Scope,
+        deep: bool,
+        owner: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            token: format!("opaquelocktoken:{}", uuid()),
+            path: path.into(),
+            root: root.into(),
+            scope,
+            deep,
+            owner,
+            timeout,
+            expires: Instant::now() + timeout,
+        }
+    }
+
+    /// Whether the lock applies to the given path.
+    pub fn covers(&self, path: &Path) -> bool {
+        path == self.path || (self.deep && path.starts_with(&self.path))
+    }
+
+    /// Whether the lock applies to the given path, or, for a deep
+    /// operation, to anything beneath it.
+    fn overlaps(&self, path: &Path, deep: bool) -> bool {
+        self.covers(path) || (deep && self.path.starts_with(path))
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.expires
+            .checked_duration_since(Instant::now())
+            .map(|dur| dur.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+fn purge(locks: &mut Vec<Lock>) {
+    let now = Instant::now();
+    locks.retain(|lock| lock.expires > now);
+}
+
+/// The lock tokens submitted in the If header of a request.
+pub fn submitted_tokens(headers: &HeaderList) -> Vec<String> {
+    headers
+        .get(IF)
+        .map(|val| {
+            TOKEN
+                .captures_iter(val)
+                .map(|caps| caps[1].to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Works out how long a lock should be held for from the Timeout
+/// header, capped to the configured maximum.
+pub fn timeout(headers: &HeaderList) -> Duration {
+    let max = CONFIG.webdav.max_lock_timeout;
+
+    let secs = headers
+        .get(TIMEOUT)
+        .and_then(|val| {
+            val.split(',').map(|val| val.trim()).find_map(|val| {
+                if val.eq_ignore_ascii_case("infinite") {
+                    Some(max)
+                } else if val.to_lowercase().starts_with("second-") {
+                    val[7..].parse().ok()
+                } else {
+                    None
+                }
+            })
+        })
+        .unwrap_or(CONFIG.webdav.lock_timeout);
+
+    Duration::from_secs(secs.min(max))
+}
+
+/// The active locks applying to the path.
+pub fn covering(path: &Path) -> Vec<Lock> {
+    let mut locks = LOCKS.write().unwrap();
+    purge(&mut locks);
+
+    locks
+        .iter()
+        .filter(|lock| lock.covers(path))
+        .cloned()
+        .collect()
+}
+
+/// Whether a change to the path, or to everything beneath it for a deep
+/// operation, is blocked by a lock whose token wasn't submitted.
+pub fn blocked(path: &Path, deep: bool, tokens: &[String]) -> bool {
+    let mut locks = LOCKS.write().unwrap();
+    purge(&mut locks);
+
+    locks
+        .iter()
+        .any(|lock| lock.overlaps(path, deep) && !tokens.contains(&lock.token))
+}
+
+/// Takes out a new lock, unless it conflicts with one already held.
+/// Shared locks only conflict with exclusive ones.
+pub fn acquire(lock: Lock) -> Option<Lock> {
+    let mut locks = LOCKS.write().unwrap();
+    purge(&mut locks);
+
+    let conflict = locks.iter().any(|held| {
+        held.overlaps(&lock.path, lock.deep)
+            && (held.scope == Scope::Exclusive
+                || lock.scope == Scope::Exclusive)
+    });
+
+    if conflict {
+        None
+    } else {
+        locks.push(lock.clone());
+        Some(lock)
+    }
+}
+
+/// Extends one of the locks applying to the path.
+pub fn refresh(
+    path: &Path,
+    tokens: &[String],
+    timeout: Duration,
+) -> Option<Lock> {
+    let mut locks = LOCKS.write().unwrap();
+    purge(&mut locks);
+
+    let lock = locks
+        .iter_mut()
+        .find(|lock| lock.covers(path) && tokens.contains(&lock.token))?;
+
+    lock.timeout = timeout;
+    lock.expires = Instant::now() + timeout;
+
+    Some(lock.clone())
+}
+
+/// Releases the lock with the given token, if it applies to the path.
+pub fn release(path: &Path, token: &str) -> bool {
+    let mut locks = LOCKS.write().unwrap();
+    purge(&mut locks);
+
+    let len = locks.len();
+    locks.retain(|lock| !(lock.token == token && lock.covers(path)));
+
+    locks.len() != len
+}
+
+/// Drops every lock on or beneath a path that no longer exists.
+pub fn forget(path: &Path) {
+    LOCKS
+        .write()
+        .unwrap()
+        .retain(|lock| !lock.path.starts_with(path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(path: &str, scope: Scope, deep: bool) -> Lock {
+        Lock::new(
+            Path::new(path),
+            path,
+            scope,
+            deep,
+            None,
+            Duration::from_secs(60),
+        )
+    }
+
+    #[test]
+    fn test_lock_conflicts() {
+        let deep = acquire(lock("/t/locks/a", Scope::Exclusive, true)).unwrap();
+        let tokens = vec![deep.token.clone()];
+
+        assert!(acquire(lock("/t/locks/a/b", Scope::Shared, false)).is_none());
+        assert!(acquire(lock("/t/locks", Scope::Exclusive, true)).is_none());
+        assert!(acquire(lock("/t/locks", Scope::Exclusive, false)).is_some());
+
+        assert!(blocked(Path::new("/t/locks/a/b"), false, &[]));
+        assert!(!blocked(Path::new("/t/locks/a/b"), false, &tokens));
+        assert!(!blocked(Path::new("/t/locks/c"), true, &[]));
+
+        assert!(acquire(lock("/t/locks/c", Scope::Shared, false)).is_some());
+        assert!(acquire(lock("/t/locks/c", Scope::Shared, false)).is_some());
+        assert!(acquire(lock("/t/locks/c", Scope::Exclusive, false)).is_none());
+
+        assert!(release(Path::new("/t/locks/a/b"), &deep.token));
+        assert!(!blocked(Path::new("/t/locks/a/b"), false, &[]));
+    }
+
+    #[test]
+    fn test_submitted_tokens() {
+        let headers: HeaderList = "If: (<opaquelocktoken:a-b>) (Not \
+                                   <opaquelocktoken:c>)"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            submitted_tokens(&headers),
+            vec!["opaquelocktoken:a-b", "opaquelocktoken:c"]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use super::locks::{self, Lock, Scope};
+use super::xml::escape;
+use crate::webserver::responses::content_type;
+use crate::webserver::shared::*;
+use crate::webserver::socket_handler::etag::*;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+pub const DAV_NS: &str = "DAV:";
+
+/// A property name, as its namespace and local name.
+pub type PropName = (String, String);
+
+lazy_static::lazy_static! {
+    static ref DEAD: RwLock<HashMap<PathBuf, BTreeMap<PropName, String>>> = {
+        Default::default()
+    };
+}
+
+/// The properties computed from the filesystem, which clients can't
+/// change.
+pub static LIVE: &[&str] = &[
+    "creationdate",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "resourcetype",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+pub fn dav(name: &str) -> PropName { (DAV_NS.into(), name.into()) }
+
+/// Renders the XML for a lock, as it appears in lockdiscovery and in
+/// the response to a LOCK request.
+pub fn active_lock(lock: &Lock) -> String {
+    format!(
+        "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/\
+         ></D:lockscope><D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:\
+         timeout><D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:\
+         href>{}</D:href></D:lockroot></D:activelock>",
+        match lock.scope {
+            Scope::Exclusive => "exclusive",
+            Scope::Shared => "shared",
+        },
+        if lock.deep { "infinity" } else { "0" },
+        lock.owner
+            .as_ref()
+            .map(|owner| format!("<D:owner>{}</D:owner>", owner))
+            .unwrap_or_default(),
+        lock.remaining(),
+        lock.token,
+        escape(&lock.root)
+    )
+}
+
+fn format_time(
+    time: std::io::Result<std::time::SystemTime>,
+) -> Option<DateTime<Utc>> {
+    time.ok().map(|time| time.into())
+}
+
+/// Computes the value of a live property of a resource, as XML. Returns
+/// None for properties that don't apply to the resource.
+pub fn live(path: &Path, name: &str) -> Option<String> {
+    let meta = path.metadata().ok()?;
+
+    match name {
+        "creationdate" => {
+            format_time(meta.created().or_else(|_| meta.modified()))
+                .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        }
+        "displayname" => {
+            path.file_name().map(|name| escape(&name.to_string_lossy()))
+        }
+        "getcontentlength" if meta.is_file() => Some(meta.len().to_string()),
+        "getcontenttype" if meta.is_file() => Some(escape(&content_type(path))),
+        "getetag" => {
+            let etag = if meta.is_dir() {
+                dir_etag(path)
+            } else {
+                file_etag(path)
+            };
+            etag.ok().map(|etag| escape(&etag))
+        }
+        "getlastmodified" => format_time(meta.modified())
+            .map(|time| HeaderList::format_date(&time)),
+        "resourcetype" => Some(if meta.is_dir() {
+            "<D:collection/>".into()
+        } else {
+            String::new()
+        }),
+        "supportedlock" => Some(
+            "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:\
+             locktype><D:write/></D:locktype></D:lockentry><D:lockentry><D:\
+             lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:\
+             locktype></D:lockentry>"
+                .into(),
+        ),
+        "lockdiscovery" => Some(
+            locks::covering(path)
+                .iter()
+                .map(active_lock)
+                .collect::<Vec<_>>()
+                .concat(),
+        ),
+        _ => None,
+    }
+}
+
+/// Every property a resource has, live ones first, with their values.
+pub fn all(path: &Path) -> Vec<(PropName, String)> {
+    let mut ret: Vec<_> = LIVE
+        .iter()
+        .filter_map(|name| live(path, name).map(|val| (dav(name), val)))
+        .collect();
+
+    ret.extend(
+        dead(path)
+            .into_iter()
+            .map(|(name, val)| (name, escape(&val))),
+    );
+
+    ret
+}
+
+/// Looks up the value of a single property.
+pub fn get(path: &Path, name: &PropName) -> Option<String> {
+    if name.0 == DAV_NS && LIVE.contains(&name.1.as_str()) {
+        live(path, &name.1)
+    } else {
+        dead(path).get(name).map(|val| escape(val))
+    }
+}
+
+/// Whether a property is computed by the server and can't be set.
+pub fn is_protected(name: &PropName) -> bool { name.0 == DAV_NS }
+
+/// The properties clients have set on a resource. These are only kept
+/// in memory, alongside the locks.
+pub fn dead(path: &Path) -> BTreeMap<PropName, String> {
+    DEAD.read().unwrap().get(path).cloned().unwrap_or_default()
+}
+
+pub fn set(path: &Path, name: PropName, val: String) {
+    DEAD.write()
+        .unwrap()
+        .entry(path.into())
+        .or_default()
+        .insert(name, val);
+}
+
+pub fn remove(path: &Path, name: &PropName) {
+    if let Some(props) = DEAD.write().unwrap().get_mut(path) {
+        props.remove(name);
+    }
+}
+
+/// Carries the properties of a resource, and everything beneath it,
+/// over to a copy of it.
+pub fn copy(from: &Path, to: &Path) {
+    let mut dead = DEAD.write().unwrap();
+
+    let copies: Vec<_> = dead
+        .iter()
+        .filter_map(|(path, props)| {
+            let rel = path.strip_prefix(from).ok()?;
+            Some((to.join(rel), props.clone()))
+        })
+        .collect();
+
+    dead.extend(copies);
+}
+
+/// Drops the properties of a resource, and everything beneath it.
+pub fn forget(path: &Path) {
+    DEAD.write()
+        .unwrap()
+        .retain(|key, _| !key.starts_with(path));
+}
@@ -0,0 +1,182 @@
+use super::props::{PropName, DAV_NS};
+use crate::webserver::responses::*;
+use crate::webserver::shared::*;
+
+use xmltree::{Element, XMLNode};
+
+pub const XML_TYPE: &str = "application/xml";
+
+pub fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            _ => ret.push(chr),
+        }
+    }
+
+    ret
+}
+
+/// Parses the XML body of a request. An empty body gives None, and
+/// anything that isn't well formed gives an error.
+pub fn parse_body(payload: Option<&Vec<u8>>) -> Result<Option<Element>, ()> {
+    match payload {
+        Some(payload) if !payload.iter().all(u8::is_ascii_whitespace) => {
+            Element::parse(&payload[..]).map(Some).map_err(|_| ())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether the element is the one with the given name in the DAV:
+/// namespace.
+pub fn is_dav(elem: &Element, name: &str) -> bool {
+    elem.name == name
+        && elem.namespace.as_ref().map_or(false, |ns| ns == DAV_NS)
+}
+
+/// The child elements of an element, skipping text and comments.
+pub fn children(elem: &Element) -> impl Iterator<Item = &Element> {
+    elem.children.iter().filter_map(|node| match node {
+        XMLNode::Element(elem) => Some(elem),
+        _ => None,
+    })
+}
+
+pub fn prop_name(elem: &Element) -> PropName {
+    (
+        elem.namespace.clone().unwrap_or_default(),
+        elem.name.clone(),
+    )
+}
+
+/// Renders a property element, with the value already being XML.
+pub fn prop(name: &PropName, val: Option<&str>) -> String {
+    let (open, close) = if name.0 == DAV_NS {
+        (format!("D:{}", name.1), format!("D:{}", name.1))
+    } else {
+        (
+            format!("R:{} xmlns:R=\"{}\"", name.1, escape(&name.0)),
+            format!("R:{}", name.1),
+        )
+    };
+
+    match val {
+        Some(val) if !val.is_empty() => {
+            format!("<{}>{}</{}>", open, val, close)
+        }
+        _ => format!("<{}/>", open),
+    }
+}
+
+fn status_line(code: &StatusCode) -> String {
+    format!("HTTP/1.1 {} {}", code.to_num(), code)
+}
+
+/// Builds up a 207 Multi-Status response, one resource at a time.
+pub struct Multistatus {
+    body: String,
+}
+
+impl Multistatus {
+    pub fn new() -> Self {
+        Self {
+            body: String::from(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus \
+                 xmlns:D=\"DAV:\">\n",
+            ),
+        }
+    }
+
+    /// Adds a resource with its properties, grouped by status.
+    pub fn propstat(
+        &mut self,
+        href: &str,
+        stats: &[(StatusCode, Vec<String>)],
+    ) {
+        self.body.push_str("<D:response>");
+        self.body
+            .push_str(&format!("<D:href>{}</D:href>", escape(href)));
+
+        for (code, props) in stats.iter().filter(|(_, props)| !props.is_empty())
+        {
+            self.body.push_str(&format!(
+                "<D:propstat><D:prop>{}</D:prop><D:status>{}</D:status></D:\
+                 propstat>",
+                props.concat(),
+                status_line(code)
+            ));
+        }
+
+        self.body.push_str("</D:response>\n");
+    }
+
+    pub fn into_response(mut self) -> Response {
+        self.body.push_str("</D:multistatus>\n");
+        xml_response(StatusCode::MultiStatus, self.body)
+    }
+}
+
+pub fn xml_response(code: StatusCode, body: String) -> Response {
+    let body: Vec<u8> = body.into();
+
+    let mut headers = HeaderList::response_headers();
+    headers.content(XML_TYPE, Some("utf-8".into()), body.len());
+
+    Response {
+        code,
+        headers,
+        data: Some(body.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prop() {
+        assert_eq!(
+            prop(&(DAV_NS.into(), "getetag".into()), Some("\"a\"")),
+            "<D:getetag>\"a\"</D:getetag>"
+        );
+        assert_eq!(
+            prop(&(DAV_NS.into(), "resourcetype".into()), None),
+            "<D:resourcetype/>"
+        );
+        assert_eq!(
+            prop(&("urn:x".into(), "color".into()), Some("red &amp; blue")),
+            "<R:color xmlns:R=\"urn:x\">red &amp; blue</R:color>"
+        );
+    }
+
+    #[test]
+    fn test_parse_body() {
+        let body = br#"<?xml version="1.0"?>
+            <D:propfind xmlns:D="DAV:" xmlns:Z="urn:z">
+              <D:prop><D:getetag/><Z:color/></D:prop>
+            </D:propfind>"#
+            .to_vec();
+
+        let elem = parse_body(Some(&body)).unwrap().unwrap();
+        assert!(is_dav(&elem, "propfind"));
+
+        let prop = children(&elem).next().unwrap();
+        let names: Vec<_> = children(prop).map(prop_name).collect();
+        assert_eq!(
+            names,
+            vec![
+                (DAV_NS.to_string(), "getetag".to_string()),
+                ("urn:z".to_string(), "color".to_string())
+            ]
+        );
+
+        assert_eq!(parse_body(Some(&b" \r\n".to_vec())), Ok(None));
+        assert!(parse_body(Some(&b"<a>".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use super::requests::Request;
+use super::responses::*;
+use super::shared::*;
+use super::socket_handler::auth_handler::AuthHandler;
+use crate::CONFIG;
+
+use chrono::{DateTime, Utc};
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+
+///What a connection is doing. Writing covers handling the request as
+///well as sending the response.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Reading,
+    Writing,
+    Idle,
+}
+
+struct Connection {
+    state:   State,
+    request: Option<String>,
+    since:   Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref STARTED: (Instant, DateTime<Utc>) = (Instant::now(), Utc::now());
+    static ref CONNECTIONS: RwLock<HashMap<SocketAddr, Connection>> = {
+        Default::default()
+    };
+}
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+///Marks the server as started, which uptime is counted from.
+pub fn start() { lazy_static::initialize(&STARTED); }
+
+pub fn opened(addr: SocketAddr) {
+    CONNECTIONS.write().unwrap().insert(
+        addr,
+        Connection {
+            state:   State::Reading,
+            request: None,
+            since:   Instant::now(),
+        },
+    );
+}
+
+pub fn closed(addr: &SocketAddr) { CONNECTIONS.write().unwrap().remove(addr); }
+
+///Moves a connection to another state, keeping the last request line
+///when no new one is given.
+pub fn set(addr: &SocketAddr, state: State, request: Option<String>) {
+    if let Some(conn) = CONNECTIONS.write().unwrap().get_mut(addr) {
+        conn.state = state;
+        conn.since = Instant::now();
+        if request.is_some() {
+            conn.request = request;
+        }
+    }
+}
+
+pub fn served() { REQUESTS.fetch_add(1, Ordering::Relaxed); }
+
+#[derive(Serialize, Debug)]
+struct ConnectionStatus {
+    peer:    String,
+    state:   State,
+    request: Option<String>,
+    seconds: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct ServerStatus {
+    started: DateTime<Utc>,
+    uptime: u64,
+    requests: u64,
+    requests_per_second: f64,
+    connections: Vec<ConnectionStatus>,
+}
+
+fn snapshot() -> ServerStatus {
+    let (started, started_at) = *STARTED;
+    let uptime = started.elapsed();
+    let requests = REQUESTS.load(Ordering::Relaxed);
+
+    let mut connections: Vec<_> = CONNECTIONS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(addr, conn)| ConnectionStatus {
+            peer:    addr.to_string(),
+            state:   conn.state,
+            request: conn.request.clone(),
+            seconds: conn.since.elapsed().as_secs_f64(),
+        })
+        .collect();
+    connections.sort_by(|a, b| a.peer.cmp(&b.peer));
+
+    ServerStatus {
+        started: started_at,
+        uptime: uptime.as_secs(),
+        requests,
+        requests_per_second: requests as f64 / uptime.as_secs_f64().max(1.0),
+        connections,
+    }
+}
+
+///Serves the status page, as JSON when the client asks for it. It
+///lists client addresses, so it's only served where an auth file has
+///checked the credentials of the request, or to allowed addresses.
+pub fn status_response(
+    req: &Request,
+    url: &Path,
+    addr: &SocketAddr,
+) -> Response {
+    let protected = AuthHandler::new(url)
+        .map(|handler| handler.requires_auth())
+        .unwrap_or(false);
+
+    if !protected && !CONFIG.server_status.allow.contains(&addr.ip()) {
+        warn!("refusing server status to '{}'", addr);
+        return Response::forbidden();
+    }
+
+    let json = req
+        .headers
+        .get(ACCEPT)
+        .map_or(false, |accept| accept.contains("application/json"));
+    if json {
+        Response::json_response(StatusCode::Ok, &snapshot())
+    } else {
+        Response::template_response(StatusCode::Ok, "status.html", &snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let addr: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        opened(addr);
+        set(&addr, State::Writing, Some("GET / HTTP/1.1".into()));
+        set(&addr, State::Idle, None);
+
+        let status = snapshot();
+        let conn = status
+            .connections
+            .iter()
+            .find(|conn| conn.peer == "192.0.2.1:5000")
+            .unwrap();
+        assert_eq!(conn.state, State::Idle);
+        assert_eq!(conn.request.as_deref(), Some("GET / HTTP/1.1"));
+
+        closed(&addr);
+        assert!(snapshot()
+            .connections
+            .iter()
+            .all(|conn| conn.peer != "192.0.2.1:5000"));
+    }
+}
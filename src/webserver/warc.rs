@@ -0,0 +1,110 @@
+mod recorder;
+mod replay;
+pub use recorder::*;
+pub use replay::*;
+
+use super::shared::uuid;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{Result as ioResult, Write};
+
+pub const WARC_VERSION: &str = "WARC/1.1";
+
+pub const HTTP_REQUEST: &str = "application/http;msgtype=request";
+pub const HTTP_RESPONSE: &str = "application/http;msgtype=response";
+
+/// The named fields of a single WARC record, in the order they are
+/// written. The Content-Length field is added when the record is
+/// written, since it depends on the block that follows.
+#[derive(Debug)]
+pub struct RecordHeader(Vec<(&'static str, String)>);
+
+impl RecordHeader {
+    pub fn new(typ: &str, id: &str, date: &DateTime<Utc>) -> Self {
+        RecordHeader(vec![
+            ("WARC-Type", typ.into()),
+            ("WARC-Record-ID", id.into()),
+            ("WARC-Date", date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ])
+    }
+
+    pub fn field(mut self, name: &'static str, val: &str) -> Self {
+        self.0.push((name, val.into()));
+        self
+    }
+
+    /// Writes the header for a block of the given length, including
+    /// the blank line separating it from the block.
+    pub fn write_to(&self, writer: &mut dyn Write, len: u64) -> ioResult<()> {
+        write!(writer, "{}\r\n", WARC_VERSION)?;
+        for (name, val) in self.0.iter() {
+            write!(writer, "{}: {}\r\n", name, val)?;
+        }
+        write!(writer, "Content-Length: {}\r\n\r\n", len)
+    }
+}
+
+/// Writes a complete record whose block is already in memory.
+pub fn write_record(
+    writer: &mut dyn Write,
+    header: &RecordHeader,
+    block: &[u8],
+) -> ioResult<()> {
+    header.write_to(writer, block.len() as u64)?;
+    writer.write_all(block)?;
+    writer.write_all(b"\r\n\r\n")
+}
+
+/// Produces a fresh record id in the `urn:uuid` form the spec
+/// recommends.
+pub fn record_id() -> String { format!("<urn:uuid:{}>", uuid()) }
+
+/// Formats a digest the way WARC tools expect it, as base32 SHA-1.
+pub fn labelled_digest(hasher: &sha1::Sha1) -> String {
+    let bytes = hasher.digest().bytes();
+
+    format!(
+        "sha1:{}",
+        base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_write_record() {
+        let date = Utc.with_ymd_and_hms(2019, 10, 1, 12, 0, 0).unwrap();
+        let header = RecordHeader::new("request", "<urn:uuid:x>", &date)
+            .field("WARC-Target-URI", "http://localhost/a.txt");
+
+        let mut out = Vec::new();
+        write_record(&mut out, &header, b"GET /a.txt HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "WARC/1.1\r\n\
+             WARC-Type: request\r\n\
+             WARC-Record-ID: <urn:uuid:x>\r\n\
+             WARC-Date: 2019-10-01T12:00:00Z\r\n\
+             WARC-Target-URI: http://localhost/a.txt\r\n\
+             Content-Length: 23\r\n\r\n\
+             GET /a.txt HTTP/1.1\r\n\r\n\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_labelled_digest() {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(b"hello");
+
+        assert_eq!(
+            labelled_digest(&hasher),
+            "sha1:VL2MMHO4YXUKFWV63YHTWSBM3GXKSQ2N"
+        );
+        assert_ne!(record_id(), record_id());
+    }
+}
@@ -0,0 +1,337 @@
+use super::*;
+use crate::webserver::requests::Request;
+use crate::webserver::responses::*;
+use crate::webserver::shared::headers::*;
+use crate::CONFIG;
+
+use log::*;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref WRITER: Mutex<Option<WarcFile>> = {
+        Default::default()
+    };
+}
+
+/// The WARC file currently being appended to.
+struct WarcFile {
+    file:   File,
+    path:   PathBuf,
+    size:   u64,
+    serial: u32,
+}
+
+impl WarcFile {
+    /// Starts a new file in the archive directory, beginning with a
+    /// warcinfo record describing the server that wrote it.
+    fn create(serial: u32) -> ioResult<Self> {
+        std::fs::create_dir_all(&CONFIG.warc.dir)?;
+
+        let now = Utc::now();
+        let path = CONFIG.warc.dir.join(format!(
+            "{}-{}-{:05}.warc",
+            CONFIG.warc.prefix,
+            now.format("%Y%m%d%H%M%S"),
+            serial
+        ));
+
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)?;
+
+        let info = format!(
+            "software: {}/{}\r\nformat: WARC File Format 1.1\r\n",
+            SERVER_NAME, SERVER_VERS
+        );
+        let header = RecordHeader::new("warcinfo", &record_id(), &now)
+            .field(
+                "WARC-Filename",
+                &path.file_name().unwrap().to_string_lossy(),
+            )
+            .field("Content-Type", "application/warc-fields");
+        write_record(&mut file, &header, info.as_bytes())?;
+
+        info!("recording exchanges to '{}'", path.display());
+
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+            path,
+            serial,
+        })
+    }
+}
+
+/// Hands out the file to append the next exchange to, moving on to a
+/// new one once the current file has grown past the configured size.
+fn with_file<F>(func: F) -> ioResult<()>
+where
+    F: FnOnce(&mut File) -> ioResult<()>,
+{
+    let mut writer = WRITER.lock().unwrap();
+
+    let serial = match *writer {
+        Some(ref warc) if warc.size < CONFIG.warc.max_size => None,
+        Some(ref warc) => {
+            debug!("rotating WARC file '{}'", warc.path.display());
+            Some(warc.serial + 1)
+        }
+        None => Some(0),
+    };
+    if let Some(serial) = serial {
+        *writer = Some(WarcFile::create(serial)?);
+    }
+
+    let warc = writer.as_mut().unwrap();
+    let res = func(&mut warc.file);
+    warc.size = warc.file.metadata()?.len();
+
+    res
+}
+
+/// Where a streamed response body is copied while it is sent, so that
+/// it can be archived afterwards without holding it in memory.
+struct Spool {
+    file:   File,
+    path:   PathBuf,
+    hasher: sha1::Sha1,
+    len:    u64,
+    failed: bool,
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) { let _ = std::fs::remove_file(&self.path); }
+}
+
+/// A reader adapter that copies everything read through it into a
+/// spool. Failing to spool never fails the response itself, the
+/// exchange just won't be recorded.
+struct TeeReader {
+    inner: Box<dyn Read>,
+    spool: Rc<RefCell<Spool>>,
+}
+
+impl Read for TeeReader {
+    fn read(&mut self, buf: &mut [u8]) -> ioResult<usize> {
+        let siz = self.inner.read(buf)?;
+
+        let mut spool = self.spool.borrow_mut();
+        if !spool.failed {
+            match spool.file.write_all(&buf[0..siz]) {
+                Ok(()) => {
+                    spool.hasher.update(&buf[0..siz]);
+                    spool.len += siz as u64;
+                }
+                Err(err) => {
+                    warn!("failed to spool response body: '{}'", err);
+                    spool.failed = true;
+                }
+            }
+        }
+
+        Ok(siz)
+    }
+}
+
+enum Body {
+    Empty,
+    Buffer(Vec<u8>),
+    Spooled(Rc<RefCell<Spool>>),
+}
+
+/// A request and its response, captured as the response is written so
+/// they can be appended to the archive as a pair of records.
+pub struct Exchange {
+    target:  String,
+    date:    DateTime<Utc>,
+    request: Vec<u8>,
+    payload: Option<Vec<u8>>,
+    status:  String,
+    headers: HeaderList,
+    body:    Body,
+}
+
+impl Exchange {
+    /// Starts capturing the exchange, if recording is enabled. This
+    /// has to be the last thing done to the response before it's
+    /// written, as a streamed body gets wrapped to copy it aside.
+    pub fn begin(req: &Request, resp: &mut Response) -> Option<Self> {
+        if !CONFIG.warc.record {
+            return None;
+        }
+
+        let body = match resp.data.take() {
+            None => Body::Empty,
+            Some(ResponseData::Buffer(buff)) => {
+                let body = Body::Buffer(buff.clone());
+                resp.data = Some(ResponseData::Buffer(buff));
+                body
+            }
+            Some(ResponseData::Stream(stream)) => match Self::spool() {
+                Ok(spool) => {
+                    let spool = Rc::new(RefCell::new(spool));
+                    resp.data =
+                        Some(ResponseData::Stream(Box::new(TeeReader {
+                            inner: stream,
+                            spool: spool.clone(),
+                        })));
+                    Body::Spooled(spool)
+                }
+                Err(err) => {
+                    warn!("not recording exchange, no spool: '{}'", err);
+                    resp.data = Some(ResponseData::Stream(stream));
+                    return None;
+                }
+            },
+        };
+
+        let host = req
+            .headers
+            .get(HOST)
+            .map(String::from)
+            .unwrap_or_else(|| format!("{}:{}", CONFIG.addr, CONFIG.port));
+        let query = if req.query.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", req.query)
+        };
+
+        Some(Self {
+            target: format!("http://{}{}{}", host, req.path.display(), query),
+            date: Utc::now(),
+            request: Self::request_head(req, &query),
+            payload: req.payload.clone(),
+            status: format!(
+                "HTTP/1.1 {} {}\r\n",
+                resp.code.to_num(),
+                resp.code
+            ),
+            headers: resp.headers.clone(),
+            body,
+        })
+    }
+
+    /// The request line and headers as they go into the archive, minus
+    /// the credentials, which anyone reading the archive could replay.
+    fn request_head(req: &Request, query: &str) -> Vec<u8> {
+        let mut headers = req.headers.clone();
+        headers.remove(AUTHORIZATION);
+        headers.remove(COOKIE);
+
+        format!(
+            "{} {}{} {}\r\n{}\r\n",
+            req.method,
+            req.path.display(),
+            query,
+            req.ver,
+            headers
+        )
+        .into_bytes()
+    }
+
+    fn spool() -> ioResult<Spool> {
+        std::fs::create_dir_all(&CONFIG.warc.dir)?;
+
+        let path = CONFIG.warc.dir.join(format!(".{}.spool", uuid()));
+
+        Ok(Spool {
+            file: OpenOptions::new()
+                .create_new(true)
+                .read(true)
+                .write(true)
+                .open(&path)?,
+            path,
+            hasher: sha1::Sha1::new(),
+            len: 0,
+            failed: false,
+        })
+    }
+
+    /// Appends the request and response records to the archive once
+    /// the response has been sent. Recording failures are logged and
+    /// otherwise ignored.
+    pub fn finish(self) {
+        let target = self.target.clone();
+        if let Err(err) = self.write() {
+            error!("failed to record exchange for '{}': '{}'", target, err);
+        }
+    }
+
+    fn write(mut self) -> ioResult<()> {
+        let mut req_hasher = sha1::Sha1::new();
+        let mut request = std::mem::take(&mut self.request);
+        if let Some(ref payload) = self.payload {
+            req_hasher.update(payload);
+            request.extend_from_slice(payload);
+        }
+
+        let bodiless = matches!(self.body, Body::Empty);
+        let mut resp_hasher = sha1::Sha1::new();
+        let (mut body, len): (Box<dyn Read>, u64) = match self.body {
+            Body::Empty => (Box::new(std::io::empty()), 0),
+            Body::Buffer(ref buff) => {
+                resp_hasher.update(buff);
+                (Box::new(&buff[..]), buff.len() as u64)
+            }
+            Body::Spooled(ref spool) => {
+                let spool = spool.borrow();
+                if spool.failed {
+                    return Ok(());
+                }
+
+                let mut file = spool.file.try_clone()?;
+                file.seek(SeekFrom::Start(0))?;
+                resp_hasher = spool.hasher.clone();
+                (Box::new(file.take(spool.len)), spool.len)
+            }
+        };
+
+        //The body is archived as it was before the transfer coding, so
+        //the recorded headers have to describe it that way.
+        if self.headers.is_chunked() {
+            self.headers.remove(TRANSFER_ENCODING);
+            self.headers.remove(TRAILER);
+            self.headers.content_length(len as usize);
+        }
+        //Responses sent without a body, like those to HEAD, still give
+        //the length of the body they left out, which would make the
+        //record look truncated.
+        if bodiless {
+            self.headers.remove(CONTENT_LENGTH);
+        }
+        let head = format!("{}{}\r\n", self.status, self.headers).into_bytes();
+
+        let req_id = record_id();
+        let req_header = RecordHeader::new("request", &req_id, &self.date)
+            .field("WARC-Target-URI", &self.target)
+            .field("Content-Type", HTTP_REQUEST)
+            .field("WARC-Payload-Digest", &labelled_digest(&req_hasher));
+
+        let resp_header =
+            RecordHeader::new("response", &record_id(), &self.date)
+                .field("WARC-Target-URI", &self.target)
+                .field("WARC-Concurrent-To", &req_id)
+                .field("Content-Type", HTTP_RESPONSE)
+                .field("WARC-Payload-Digest", &labelled_digest(&resp_hasher));
+
+        with_file(|file| {
+            //Both records are written in one go so that readers always
+            //find a response right after its request.
+            let mut out = std::io::BufWriter::new(file);
+            write_record(&mut out, &req_header, &request)?;
+
+            resp_header.write_to(&mut out, head.len() as u64 + len)?;
+            out.write_all(&head)?;
+            std::io::copy(&mut body, &mut out)?;
+            out.write_all(b"\r\n\r\n")?;
+
+            out.flush()
+        })
+    }
+}
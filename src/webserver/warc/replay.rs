@@ -0,0 +1,415 @@
+use super::*;
+use crate::webserver::requests::Request;
+use crate::webserver::responses::*;
+use crate::webserver::shared::headers::*;
+use crate::CONFIG;
+
+use log::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+lazy_static::lazy_static! {
+    static ref INDEX: HashMap<String, Vec<Capture>> = build_index();
+}
+
+/// Headers that only described the connection the response was
+/// archived from, and must not be replayed on this one.
+static HOP_BY_HOP: &[&str] = &[
+    CONNECTION,
+    "keep-alive",
+    TRANSFER_ENCODING,
+    TRAILER,
+    CONTENT_LENGTH,
+];
+
+/// A single archived response, holding what a line of a CDX index
+/// would, along with where to find the HTTP message again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub key:      String,
+    pub original: String,
+    pub date:     DateTime<Utc>,
+    pub status:   usize,
+    pub mime:     String,
+    pub file:     PathBuf,
+    pub offset:   u64,
+    pub length:   u64,
+}
+
+impl Display for Capture {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "{} {} {} {} {} {} {} {}",
+            self.key,
+            self.date.format("%Y%m%d%H%M%S"),
+            self.original,
+            self.mime,
+            self.status,
+            self.length,
+            self.offset,
+            self.file.display()
+        )
+    }
+}
+
+/// The status line and headers at the start of an archived response.
+struct HttpHead {
+    status:  usize,
+    reason:  String,
+    headers: Vec<(String, String)>,
+    len:     u64,
+}
+
+/// Whether the server is serving an archive instead of its root.
+pub fn replaying() -> bool { !CONFIG.warc.replay.is_empty() }
+
+/// Builds the index of the configured archives up front, instead of on
+/// the first request.
+pub fn load_index() { lazy_static::initialize(&INDEX); }
+
+/// Looks up the key a request or an archived URL is indexed under,
+/// which is its path and query. The host is ignored since the archive
+/// stands in for the document root.
+fn url_key(path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) if !query.is_empty() => format!("{}?{}", path, query),
+        _ => path.into(),
+    }
+}
+
+fn build_index() -> HashMap<String, Vec<Capture>> {
+    let mut index: HashMap<String, Vec<Capture>> = HashMap::new();
+
+    for path in CONFIG.warc.replay.iter() {
+        if path.extension().map_or(false, |ext| ext == "gz") {
+            warn!(
+                "skipping '{}', compressed archives are not supported",
+                path.display()
+            );
+            continue;
+        }
+
+        let captures = File::open(path)
+            .and_then(|file| index_archive(BufReader::new(file), path));
+
+        match captures {
+            Ok(captures) => {
+                info!(
+                    "indexed {} responses from '{}'",
+                    captures.len(),
+                    path.display()
+                );
+
+                for capture in captures {
+                    trace!("{}", capture);
+                    index.entry(capture.key.clone()).or_default().push(capture);
+                }
+            }
+            Err(err) => {
+                error!("failed to index '{}': '{}'", path.display(), err)
+            }
+        }
+    }
+
+    for captures in index.values_mut() {
+        captures.sort_by_key(|capture| capture.date);
+    }
+
+    index
+}
+
+/// Reads a line, tolerating bytes that aren't valid UTF-8. Returns the
+/// trimmed line and how many bytes it took up.
+fn read_line<R: BufRead>(reader: &mut R) -> ioResult<Option<(String, u64)>> {
+    let mut buff = Vec::new();
+    let siz = reader.read_until(b'\n', &mut buff)?;
+
+    if siz == 0 {
+        Ok(None)
+    } else {
+        let line = String::from_utf8_lossy(&buff).trim_end().to_string();
+        Ok(Some((line, siz as u64)))
+    }
+}
+
+/// Reads header lines up to the blank line ending them.
+fn read_fields<R: BufRead>(
+    reader: &mut R,
+) -> ioResult<(Vec<(String, String)>, u64)> {
+    let mut fields = Vec::new();
+    let mut len = 0;
+
+    while let Some((line, siz)) = read_line(reader)? {
+        len += siz;
+        if line.is_empty() {
+            break;
+        }
+
+        let mut split = line.splitn(2, ':');
+        if let (Some(name), Some(val)) = (split.next(), split.next()) {
+            fields.push((name.trim().to_lowercase(), val.trim().to_string()));
+        }
+    }
+
+    Ok((fields, len))
+}
+
+fn read_http_head<R: BufRead>(reader: &mut R) -> ioResult<HttpHead> {
+    use std::io::{Error, ErrorKind};
+
+    let (line, line_len) = read_line(reader)?
+        .ok_or_else(|| Error::from(ErrorKind::UnexpectedEof))?;
+
+    let mut parts = line.splitn(3, ' ');
+    let status = parts
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("bad status line: '{}'", line),
+            )
+        })?;
+    let reason = parts.next().unwrap_or_default().to_string();
+
+    let (headers, len) = read_fields(reader)?;
+
+    Ok(HttpHead {
+        status,
+        reason,
+        headers,
+        len: line_len + len,
+    })
+}
+
+/// Walks the records of an archive, picking out the HTTP responses.
+fn index_archive<R: BufRead + Seek>(
+    mut reader: R,
+    file: &Path,
+) -> ioResult<Vec<Capture>> {
+    let mut captures = Vec::new();
+    let mut offset = 0;
+
+    while let Some((line, siz)) = read_line(&mut reader)? {
+        offset += siz;
+        //Records are separated by blank lines.
+        if line.is_empty() {
+            continue;
+        }
+
+        let (fields, len) = read_fields(&mut reader)?;
+        offset += len;
+
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, val)| val.as_str())
+        };
+
+        let length: u64 = field(CONTENT_LENGTH)
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+
+        let is_response = field("warc-type") == Some("response")
+            && field(CONTENT_TYPE)
+                .map_or(false, |typ| typ.starts_with("application/http"));
+
+        let target = field("warc-target-uri").and_then(|uri| {
+            url::Url::parse(uri.trim_matches(|c| c == '<' || c == '>')).ok()
+        });
+        let date = field("warc-date")
+            .and_then(|date| date.parse::<DateTime<Utc>>().ok());
+
+        if let (true, Some(target), Some(date)) = (is_response, target, date) {
+            let head = read_http_head(&mut reader)?;
+            let path = urlencoding::decode(target.path())
+                .unwrap_or_else(|_| target.path().into());
+
+            captures.push(Capture {
+                key: url_key(&path, target.query()),
+                original: target.to_string(),
+                date,
+                status: head.status,
+                mime: head
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key == CONTENT_TYPE)
+                    .map(|(_, val)| val.clone())
+                    .unwrap_or_else(|| "unk".into()),
+                file: file.into(),
+                offset,
+                length,
+            });
+        }
+
+        offset += length;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+
+    Ok(captures)
+}
+
+/// Undoes the chunked transfer coding of a body archived as it came
+/// off the wire. Trailers are dropped.
+fn dechunk(data: &[u8]) -> Vec<u8> {
+    let mut reader = data;
+    let mut ret = Vec::new();
+
+    while let Ok(Some((line, _))) = read_line(&mut reader) {
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) if size > 0 => size.min(reader.len()),
+            _ => break,
+        };
+
+        ret.extend_from_slice(&reader[0..size]);
+        reader = &reader[size..];
+        let _ = read_line(&mut reader);
+    }
+
+    ret
+}
+
+/// Rebuilds the archived response, with its original status, headers
+/// and body. The body is streamed out of the archive.
+fn capture_response(capture: &Capture) -> ioResult<Response> {
+    let mut file = File::open(&capture.file)?;
+    file.seek(SeekFrom::Start(capture.offset))?;
+
+    let mut reader = BufReader::new(file);
+    let head = read_http_head(&mut reader)?;
+    let mut body = reader.take(capture.length.saturating_sub(head.len));
+
+    let mut headers = HeaderList::default();
+    let mut chunked = false;
+    for (name, val) in head.headers.iter() {
+        if name == TRANSFER_ENCODING && val.to_lowercase().contains("chunked") {
+            chunked = true;
+        }
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+            headers.set(name, val);
+        }
+    }
+
+    let data: ResponseData = if chunked {
+        let mut buff = Vec::new();
+        body.read_to_end(&mut buff)?;
+        let buff = dechunk(&buff);
+
+        headers.content_length(buff.len());
+        buff.into()
+    } else {
+        headers.content_length(body.limit() as usize);
+        ResponseData::Stream(Box::new(body))
+    };
+
+    headers.memento_datetime(&capture.date);
+    headers.link(format!("<{}>; rel=\"original\"", capture.original));
+
+    let code = match StatusCode::from_num(head.status) {
+        StatusCode::Custom(_, num) => StatusCode::Custom(head.reason, num),
+        code => code,
+    };
+
+    Ok(Response {
+        code,
+        headers,
+        data: Some(data),
+    })
+}
+
+/// Answers a request out of the archive. When a URL was captured more
+/// than once the latest capture is served, unless the client asks for
+/// another point in time with Accept-Datetime.
+pub fn replay_response(req: &Request) -> Response {
+    let key = url_key(&req.path.display().to_string(), Some(&req.query));
+
+    let captures = match INDEX.get(&key) {
+        Some(captures) if !captures.is_empty() => captures,
+        _ => return Response::not_found(),
+    };
+
+    let candidates = captures
+        .iter()
+        .map(|capture| (capture.date, capture))
+        .collect();
+    let capture = ContentNegotiator::new(&req.path, &req.headers)
+        .best_datetime(candidates)
+        .map(|(_, capture)| capture)
+        .unwrap_or_else(|| captures.last().unwrap());
+
+    debug!("replaying capture: {}", capture);
+
+    match capture_response(capture) {
+        Ok(mut resp) => {
+            if captures.len() > 1 {
+                resp.headers.vary(ACCEPT_DATETIME);
+            }
+            resp
+        }
+        Err(err) => {
+            error!("failed to replay '{}': '{}'", capture.original, err);
+            Response::internal_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_index_archive() {
+        let date = Utc.with_ymd_and_hms(2019, 10, 1, 12, 0, 0).unwrap();
+        let mut warc = Vec::new();
+
+        let info = RecordHeader::new("warcinfo", &record_id(), &date);
+        write_record(&mut warc, &info, b"software: test\r\n").unwrap();
+
+        let request = RecordHeader::new("request", &record_id(), &date)
+            .field("WARC-Target-URI", "http://example.com/a%20b.txt?x=1")
+            .field("Content-Type", HTTP_REQUEST);
+        write_record(
+            &mut warc,
+            &request,
+            b"GET /a%20b.txt?x=1 HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+
+        let block = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nhello";
+        let response = RecordHeader::new("response", &record_id(), &date)
+            .field("WARC-Target-URI", "http://example.com/a%20b.txt?x=1")
+            .field("Content-Type", HTTP_RESPONSE);
+        write_record(&mut warc, &response, block).unwrap();
+
+        let captures =
+            index_archive(Cursor::new(&warc), Path::new("t.warc")).unwrap();
+        assert_eq!(captures.len(), 1);
+
+        let capture = &captures[0];
+        assert_eq!(capture.key, "/a b.txt?x=1");
+        assert_eq!(capture.date, date);
+        assert_eq!(capture.status, 200);
+        assert_eq!(capture.mime, "text/plain");
+        assert_eq!(capture.length, block.len() as u64);
+
+        let start = capture.offset as usize;
+        assert_eq!(&warc[start..start + block.len()], &block[..]);
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(
+            dechunk(b"5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n"),
+            b"hello world"
+        );
+        assert_eq!(dechunk(b"0\r\n\r\n"), b"");
+    }
+}